@@ -1,20 +1,37 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use structopt::StructOpt;
 use thiserror::Error;
 use eyre::{WrapErr, Result};
 use log::trace;
 use crate::columns::Columns;
+use crate::encoding::Encoding;
 use crate::table::TableBuilder;
-use crate::core::TableQuotient;
+use crate::core::{TableQuotient, KeySplit, Matching, SubsetSearch};
+use crate::expr::Term;
+use crate::record::Mapping;
+use crate::output::{OutputFormat, ColorChoice};
 
 #[derive(Error,Debug)]
 pub enum Error {
     #[error("Only one standard input is acceptable")]
     MultipleStdin,
+    #[error("A single ASCII character is expected: {0}")]
+    InvalidCharacter(String),
+}
+
+/// Parses a single ASCII character; `\t` means a tab
+fn parse_char(s: &str) -> Result<u8, Error> {
+    match s.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        [c] if c.is_ascii() => Ok(*c),
+        _ => Err(Error::InvalidCharacter(s.to_owned())),
+    }
 }
 
 /// Compare two CSV files 
+///
+/// The exit status is 0 if the tables are same, 1 if they differ, and 2 on errors.
 #[derive(Debug,StructOpt)]
 pub struct App {
     /// Path to a CSV file
@@ -35,9 +52,147 @@ pub struct App {
     /// Define target file columns
     #[structopt(long)]
     target_columns: String,
+    /// Treat the first line of each source file as a header
+    ///
+    /// Column names in `--source-columns` are looked up in the header,
+    /// so the order of the columns in the file does not matter.
+    #[structopt(long)]
+    source_has_header: bool,
+    /// Treat the first line of each target file as a header
+    ///
+    /// Column names in `--target-columns` are looked up in the header,
+    /// so the order of the columns in the file does not matter.
+    #[structopt(long)]
+    target_has_header: bool,
+    /// Character encoding of source files
+    ///
+    /// Labels such as `utf-8`, `shift_jis`, `euc-jp` and `utf-16le` are accepted.
+    /// `auto` detects an encoding from a byte order mark or the contents.
+    #[structopt(long)]
+    source_encoding: Option<Encoding>,
+    /// Character encoding of target files
+    ///
+    /// Labels such as `utf-8`, `shift_jis`, `euc-jp` and `utf-16le` are accepted.
+    /// `auto` detects an encoding from a byte order mark or the contents.
+    #[structopt(long)]
+    target_encoding: Option<Encoding>,
+    /// Field delimiter of source files (`\t` for tab)
+    #[structopt(long, default_value = ",", parse(try_from_str = parse_char))]
+    source_delimiter: u8,
+    /// Field delimiter of target files (`\t` for tab)
+    #[structopt(long, default_value = ",", parse(try_from_str = parse_char))]
+    target_delimiter: u8,
+    /// Quote character of source files
+    #[structopt(long, default_value = "\"", parse(try_from_str = parse_char))]
+    source_quote: u8,
+    /// Quote character of target files
+    #[structopt(long, default_value = "\"", parse(try_from_str = parse_char))]
+    target_quote: u8,
+    /// Escape character for quotes in quoted fields of source files
+    #[structopt(long, parse(try_from_str = parse_char))]
+    source_escape: Option<u8>,
+    /// Escape character for quotes in quoted fields of target files
+    #[structopt(long, parse(try_from_str = parse_char))]
+    target_escape: Option<u8>,
+    /// Do not treat two consecutive quotes as an escaped quote in source files
+    #[structopt(long)]
+    source_no_double_quote: bool,
+    /// Do not treat two consecutive quotes as an escaped quote in target files
+    #[structopt(long)]
+    target_no_double_quote: bool,
+    /// Ignore lines of source files starting with this character
+    #[structopt(long, parse(try_from_str = parse_char))]
+    source_comment: Option<u8>,
+    /// Ignore lines of target files starting with this character
+    #[structopt(long, parse(try_from_str = parse_char))]
+    target_comment: Option<u8>,
+    /// Number of lines skipped at the beginning of each source file
+    #[structopt(long, default_value = "0")]
+    source_skip_lines: usize,
+    /// Number of lines skipped at the beginning of each target file
+    #[structopt(long, default_value = "0")]
+    target_skip_lines: usize,
+    /// Read only source records satisfying a condition like `amount != 0 and not description matches "^Opening"`
+    #[structopt(long, allow_hyphen_values = true)]
+    source_filter: Option<String>,
+    /// Read only target records satisfying a condition
+    #[structopt(long, allow_hyphen_values = true)]
+    target_filter: Option<String>,
     /// Determine whether records are same or not
+    #[structopt(long, allow_hyphen_values = true, required_unless = "key", conflicts_with = "key")]
+    predicate: Option<String>,
+    /// Identify records by this predicate instead of `--predicate`
+    ///
+    /// Records with the same key and different values of `--compare` are reported as modified.
+    #[structopt(long, allow_hyphen_values = true)]
+    key: Option<String>,
+    /// Compare values of records with the same key
+    #[structopt(long, allow_hyphen_values = true, requires = "key")]
+    compare: Option<String>,
+    /// Match records of the same point approximately like `price ~ price within 0.01`
+    ///
+    /// Records are paired closest first.
+    /// This option can be given multiple times.
+    #[structopt(long, allow_hyphen_values = true, number_of_values = 1)]
+    approx: Vec<String>,
+    /// Compare sums of records grouped by the predicate like `-price = price`
+    ///
+    /// Groups whose sums disagree are reported with all of their records.
+    #[structopt(long, allow_hyphen_values = true, conflicts_with_all = &["approx", "key"])]
+    sum: Option<String>,
+    /// Propose sets of unmatched records whose sum equals an unmatched record like `-price = price`
+    #[structopt(long, allow_hyphen_values = true)]
+    subset_sum: Option<String>,
+    /// Restrict members of a set by a condition with the counterpart like `date ~ date within 3 days`
+    ///
+    /// This option can be given multiple times.
+    #[structopt(long, allow_hyphen_values = true, number_of_values = 1, requires = "subset-sum")]
+    subset_within: Vec<String>,
+    /// Maximum number of records in a set proposed by `--subset-sum`
+    #[structopt(long, default_value = "3")]
+    subset_max_size: usize,
+    /// Time budget of `--subset-sum` in milliseconds
+    #[structopt(long, default_value = "1000")]
+    subset_time_limit: u64,
+    /// Treat null values as equal to each other
+    ///
+    /// By default, records with null values in the predicate never match.
+    #[structopt(long)]
+    null_equals_null: bool,
+    /// Treat null values in decimal columns as zero in the predicate
+    #[structopt(long)]
+    null_as_zero: bool,
+    /// Print nothing and only report whether the tables differ by the exit status
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Output format: `plain`, `json` or `jsonl`
+    #[structopt(long, default_value = "plain", possible_values = &["plain", "json", "jsonl"])]
+    format: OutputFormat,
+    /// Print all records of different classes instead of surplus records
+    #[structopt(long)]
+    full_classes: bool,
+    /// Print summary statistics after differences in the plain output
+    #[structopt(long)]
+    summary: bool,
+    /// Colorize the plain output: `auto`, `always` or `never`
+    ///
+    /// `auto` colorizes only if stdout is a terminal and `NO_COLOR` is not set.
+    #[structopt(long, default_value = "auto", possible_values = &["auto", "always", "never"])]
+    color: ColorChoice,
+    /// Write source records without counterparts to this CSV file
+    #[structopt(long)]
+    source_only_out: Option<PathBuf>,
+    /// Write target records without counterparts to this CSV file
+    #[structopt(long)]
+    target_only_out: Option<PathBuf>,
+    /// Write source records with counterparts to this CSV file
+    ///
+    /// Target records with counterparts are written by `--target-matched-out`.
     #[structopt(long)]
-    predicate: String,
+    matched_out: Option<PathBuf>,
+    /// Write target records with counterparts to this CSV file
+    #[structopt(long)]
+    target_matched_out: Option<PathBuf>,
     /// Ignore field length mismatches
     #[structopt(long)]
     ignore_length_mismatch: bool,
@@ -68,24 +223,143 @@ impl App {
         self.validate_path()
     }
 
-    pub fn to_quotients(&self) -> Result<(TableQuotient, TableQuotient)> {
-        let _ = self.validate()?;
+    pub fn quiet(&self) -> bool {
+        self.quiet
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    pub fn full_classes(&self) -> bool {
+        self.full_classes
+    }
+
+    pub fn summary(&self) -> bool {
+        self.summary
+    }
+
+    pub fn color(&self) -> ColorChoice {
+        self.color
+    }
+
+    pub fn source_only_out(&self) -> Option<&Path> {
+        self.source_only_out.as_deref()
+    }
+
+    pub fn target_only_out(&self) -> Option<&Path> {
+        self.target_only_out.as_deref()
+    }
+
+    pub fn matched_out(&self) -> Option<&Path> {
+        self.matched_out.as_deref()
+    }
+
+    pub fn target_matched_out(&self) -> Option<&Path> {
+        self.target_matched_out.as_deref()
+    }
+
+    pub fn null_equals_null(&self) -> bool {
+        self.null_equals_null
+    }
+
+    /// Parses `--predicate`, or `--key` and `--compare` into terms of both sides
+    fn parse_predicate(&self, source_columns: &Columns, target_columns: &Columns) -> Result<(Vec<Term>, Vec<Term>, Option<KeySplit>)> {
+        let key = match self.key {
+            Some(ref key) => key,
+            None => {
+                let predicate = self.predicate.as_deref().unwrap_or_default();
+                let (source_terms, target_terms) = crate::expr::parse_equal(predicate, source_columns, target_columns)
+                    .wrap_err("Failed to parse the value of `--predicate`")?;
+                return Ok((source_terms, target_terms, None));
+            },
+        };
+
+        let (mut source_terms, mut target_terms) = crate::expr::parse_equal(key, source_columns, target_columns)
+            .wrap_err("Failed to parse the value of `--key`")?;
+        let key_len = source_terms.len();
+
+        let (source_compared, target_compared) = match self.compare {
+            Some(ref compare) => crate::expr::parse_equal(compare, source_columns, target_columns)
+                .wrap_err("Failed to parse the value of `--compare`")?,
+            None => (Vec::new(), Vec::new()),
+        };
+        let names = source_compared.iter()
+            .map(|term| term.describe(source_columns))
+            .collect();
+        source_terms.extend(source_compared);
+        target_terms.extend(target_compared);
+
+        Ok((source_terms, target_terms, Some(KeySplit::new(key_len, names))))
+    }
+
+    pub fn to_quotients(&self) -> Result<(TableQuotient, TableQuotient, Matching)> {
+        self.validate()?;
 
         let source_columns = self.source_columns.parse::<Columns>()
             .wrap_err("Failed to parse field names and types of source table")?;
         let target_columns = self.target_columns.parse::<Columns>()
             .wrap_err("Failed to parse field names and types of target table")?;
 
-        let (source_mapping, target_mapping) = crate::expr::parse_equal(&self.predicate, &source_columns, &target_columns)
-            .wrap_err("Failed to parse the value of `--predicate`")?;
+        let (source_mapping, target_mapping, key_split) = self.parse_predicate(&source_columns, &target_columns)?;
+        let approx = self.approx.iter()
+            .map(|s| crate::expr::parse_approx(s, &source_columns, &target_columns))
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("Failed to parse the value of `--approx`")?;
+        let sum_equal = self.sum.as_ref()
+            .map(|s| crate::expr::parse_sum_equal(s, &source_columns, &target_columns))
+            .transpose()
+            .wrap_err("Failed to parse the value of `--sum`")?;
+        let subset_search = match self.subset_sum {
+            Some(ref s) => {
+                let sum_equal = crate::expr::parse_sum_equal(s, &source_columns, &target_columns)
+                    .wrap_err("Failed to parse the value of `--subset-sum`")?;
+                let within = self.subset_within.iter()
+                    .map(|s| crate::expr::parse_approx(s, &source_columns, &target_columns))
+                    .collect::<Result<Vec<_>, _>>()
+                    .wrap_err("Failed to parse the value of `--subset-within`")?;
+                Some(SubsetSearch::new(sum_equal)
+                    .within(within)
+                    .max_size(self.subset_max_size)
+                    .time_limit(std::time::Duration::from_millis(self.subset_time_limit)))
+            },
+            None => None,
+        };
+
+        let source_filter = self.source_filter.as_ref()
+            .map(|s| crate::expr::parse_filter(s, &source_columns))
+            .transpose()
+            .wrap_err("Failed to parse the value of `--source-filter`")?;
+        let target_filter = self.target_filter.as_ref()
+            .map(|s| crate::expr::parse_filter(s, &target_columns))
+            .transpose()
+            .wrap_err("Failed to parse the value of `--target-filter`")?;
+
+        let mut source_mapping = Mapping::from(source_mapping);
+        let mut target_mapping = Mapping::from(target_mapping);
+        if self.null_as_zero {
+            source_mapping = source_mapping.null_as_zero(&source_columns);
+            target_mapping = target_mapping.null_as_zero(&target_columns);
+        }
 
         trace!("source columns: {:?}", source_columns);
         trace!("source mapping: {:?}", source_mapping);
         trace!("target columns: {:?}", target_columns);
         trace!("target mapping: {:?}", target_mapping);
+        trace!("source filter: {:?}", source_filter);
+        trace!("target filter: {:?}", target_filter);
 
         let source_table = TableBuilder::new()
             .columns(source_columns)
+            .has_header(self.source_has_header)
+            .encoding(self.source_encoding)
+            .delimiter(self.source_delimiter)
+            .quote(self.source_quote)
+            .escape(self.source_escape)
+            .double_quote(!self.source_no_double_quote)
+            .comment(self.source_comment)
+            .skip_lines(self.source_skip_lines)
+            .filter(source_filter)
             .ignore_length_mismatch(self.ignore_length_mismatch)
             .ignore_parse_error(self.ignore_parse_error)
             .from_path(&self.source_file)
@@ -93,11 +367,22 @@ impl App {
 
         let target_table = TableBuilder::new()
             .columns(target_columns)
+            .has_header(self.target_has_header)
+            .encoding(self.target_encoding)
+            .delimiter(self.target_delimiter)
+            .quote(self.target_quote)
+            .escape(self.target_escape)
+            .double_quote(!self.target_no_double_quote)
+            .comment(self.target_comment)
+            .skip_lines(self.target_skip_lines)
+            .filter(target_filter)
             .ignore_length_mismatch(self.ignore_length_mismatch)
+            .ignore_parse_error(self.ignore_parse_error)
             .from_path(&self.target_file)
-            .wrap_err("Failed to read source table")?;
+            .wrap_err("Failed to read target table")?;
 
-        Ok((TableQuotient::new(&source_table, &source_mapping.into()),
-            TableQuotient::new(&target_table, &target_mapping.into())))
+        Ok((TableQuotient::new(&source_table, &source_mapping),
+            TableQuotient::new(&target_table, &target_mapping),
+            Matching { key_split, approx, sum_equal, subset_search }))
     }
 }
@@ -4,14 +4,71 @@ use thiserror::Error;
 #[derive(Error,Debug)]
 pub enum ParseError {
     #[error("type parse error")]
-    InvalidType(#[from] strum::ParseError),
+    InvalidType(#[from] crate::ParseTagError),
     #[error("invalid field specifier")]
     InvalidFieldSpec,
+    #[error("field positions start from 1")]
+    InvalidPosition,
+}
+
+/// Location of a column in a CSV record
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Field {
+    /// Zero-based position of a field
+    Position(usize),
+    /// Name of a field in the header
+    Header(String),
+}
+
+impl std::str::FromStr for Field {
+    type Err = self::ParseError;
+
+    /// Parses a one-based position or a header name
+    ///
+    /// A name in single quotes like `'2020'` is always a header name.
+    fn from_str(s: &str) -> Result<Field, Self::Err> {
+        if let Some(name) = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+            return Ok(Field::Header(name.to_owned()));
+        }
+        match s.parse::<usize>() {
+            Ok(0) => Err(ParseError::InvalidPosition),
+            Ok(pos) => Ok(Field::Position(pos - 1)),
+            Err(_) => Ok(Field::Header(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Column {
+    name: String,
+    tag: Tag,
+    nullable: bool,
+    field: Option<Field>,
+}
+
+impl Column {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    /// Returns `true` if empty fields are read as null
+    pub fn is_nullable(&self) -> bool {
+        self.nullable
+    }
+
+    /// Explicitly specified location of the column
+    pub fn field(&self) -> Option<&Field> {
+        self.field.as_ref()
+    }
 }
 
 #[derive(Debug,Clone)]
 pub struct Columns {
-    columns: Vec<(String, Tag)>,
+    columns: Vec<Column>,
 }
 
 impl Columns {
@@ -19,19 +76,30 @@ impl Columns {
         self.columns.len()
     }
 
-    pub fn iter<'a>(&'a self) -> std::slice::Iter<'a, (String, Tag)> {
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Column> {
         self.columns.iter()
     }
 
-    pub fn get_by_name<T: AsRef<str>>(&self, s: T) -> Option<(usize, Tag)> {
+    pub fn get_by_name<T: AsRef<str>>(&self, s: T) -> Option<(usize, &Tag)> {
         let pos = self.columns.iter()
-            .position(|(name, _)| name == s.as_ref())?;
-        Some((pos, self.columns[pos].1))
+            .position(|col| col.name == s.as_ref())?;
+        Some((pos, &self.columns[pos].tag))
     }
 
-    pub fn get_by_index(&self, index: usize) -> Option<(&str, Tag)> {
+    pub fn get_by_index(&self, index: usize) -> Option<(&str, &Tag)> {
         self.columns.get(index)
-            .map(|(name, tag)| (name.as_ref(), *tag))
+            .map(|col| (col.name.as_ref(), &col.tag))
+    }
+
+    /// Returns `true` if some column specifies its location explicitly
+    ///
+    /// Otherwise the columns are assumed to cover all fields of a record.
+    pub fn is_selective(&self) -> bool {
+        self.columns.iter().any(|col| col.field.is_some())
     }
 }
 
@@ -41,43 +109,79 @@ impl std::str::FromStr for Columns {
     fn from_str(s: &str) -> Result<Columns, Self::Err> {
         parse_columns(s)?
             .into_iter()
-            .map(|(name, tag)| {
-                match tag.parse::<Tag>() {
-                    Ok(tag) => Ok((name, tag)),
-                    Err(e) => Err(e.into()),
-                }
+            .map(|(field, name, tag)| {
+                let field = match field {
+                    Some(field) => Some(field.parse::<Field>()?),
+                    None => None,
+                };
+                let (tag, nullable) = match tag.strip_suffix('?') {
+                    Some(tag) => (tag, true),
+                    None => (tag.as_str(), false),
+                };
+                Ok(Column {
+                    name,
+                    tag: tag.parse::<Tag>()?,
+                    nullable,
+                    field,
+                })
             })
-            .collect::<Result<Vec<(String, Tag)>, Self::Err>>()
+            .collect::<Result<Vec<Column>, Self::Err>>()
             .map(|col| Columns { columns: col })
     }
 }
 
-fn parse_name_type(s: &str) -> Result<(String, String), ParseError> {
-    let fields: Vec<&str> = s.split(':')
+/// Splits `s` by `sep` except in parentheses and single quotes
+///
+/// Quotes are recognized in parentheses or at the beginning of a part,
+/// so that names like `Owner's` can be written without quotes.
+fn split_top_level(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut start = 0;
+    for (pos, c) in s.char_indices() {
+        match c {
+            '\'' if quoted || depth > 0 || s[start..pos].trim().is_empty() => quoted = !quoted,
+            _ if quoted => {},
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            c if c == sep && depth == 0 => {
+                parts.push(&s[start..pos]);
+                start = pos + c.len_utf8();
+            },
+            _ => {},
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Parses a column specifier of the form `[field:]name[:type[?]]`
+fn parse_name_type(s: &str) -> Result<(Option<String>, String, String), ParseError> {
+    let fields: Vec<&str> = split_top_level(s, ':')
+        .into_iter()
         .map(|s| s.trim())
         .collect();
 
-    if !(fields.len() == 2 || fields.len() == 1) {
-        return Err(ParseError::InvalidFieldSpec);
-    }
-
-    if fields.len() == 1 {
-        Ok((fields[0].to_owned(), "String".to_owned()))
-    } else if fields.len() == 2 {
-        Ok((fields[0].to_owned(), fields[1].to_owned()))
-    } else {
-        unreachable!()
+    match fields.len() {
+        1 => Ok((None, fields[0].to_owned(), "String".to_owned())),
+        2 => Ok((None, fields[0].to_owned(), fields[1].to_owned())),
+        3 => Ok((Some(fields[0].to_owned()), fields[1].to_owned(), fields[2].to_owned())),
+        _ => Err(ParseError::InvalidFieldSpec),
     }
 }
 
-fn parse_columns<'a>(s: &'a str) -> Result<Vec<(String, String)>, ParseError> {
+type ColumnSpec = (Option<String>, String, String);
+
+fn parse_columns(s: &str) -> Result<Vec<ColumnSpec>, ParseError> {
     let s = s.trim();
-    if s.len() == 0 {
+    if s.is_empty() {
         Ok(Vec::new())
     } else {
-        s.split(',')
+        split_top_level(s, ',')
+            .into_iter()
             .map(|s| s.trim())
-            .map(|s| parse_name_type(s))
+            .map(parse_name_type)
             .collect()
     }
 }
@@ -91,11 +195,11 @@ mod test {
         
         let cols: Columns = "key1:String, key2:Decimal".parse().unwrap();
 
-        assert_eq!(cols.get_by_name("key1"), Some((0, Tag::String)));
-        assert_eq!(cols.get_by_name("key2"), Some((1, Tag::Decimal)));
+        assert_eq!(cols.get_by_name("key1"), Some((0, &Tag::String)));
+        assert_eq!(cols.get_by_name("key2"), Some((1, &Tag::Decimal(Default::default()))));
 
-        assert_eq!(cols.get_by_index(0), Some(("key1", Tag::String)));
-        assert_eq!(cols.get_by_index(1), Some(("key2", Tag::Decimal)));
+        assert_eq!(cols.get_by_index(0), Some(("key1", &Tag::String)));
+        assert_eq!(cols.get_by_index(1), Some(("key2", &Tag::Decimal(Default::default()))));
 
         let cols = "".parse::<Columns>().unwrap();
         assert_eq!(cols.iter().next(), None);
@@ -103,19 +207,57 @@ mod test {
 
     #[test]
     fn parse_columns() {
-        assert_eq!(super::parse_columns("key1:string,key2:decimal"),
-        vec![("key1".to_owned(), "string".to_owned()),
-             ("key2".to_owned(), "decimal".to_owned())]
+        assert_eq!(super::parse_columns("key1:string,key2:decimal").unwrap(),
+        vec![(None, "key1".to_owned(), "string".to_owned()),
+             (None, "key2".to_owned(), "decimal".to_owned())]
         );
-        assert_eq!(super::parse_columns("key1: string,  key2: decimal  "),
-        vec![("key1".to_owned(), "string".to_owned()),
-             ("key2".to_owned(), "decimal".to_owned())]
+        assert_eq!(super::parse_columns("key1: string,  key2: decimal  ").unwrap(),
+        vec![(None, "key1".to_owned(), "string".to_owned()),
+             (None, "key2".to_owned(), "decimal".to_owned())]
         );
-        assert_eq!(super::parse_columns("key1,,key3"),
-        vec![("key1".to_owned(), "String".to_owned()),
-             (String::new(), "String".to_owned()),
-             ("key3".to_owned(), "String".to_owned())]);
-        assert_eq!(super::parse_columns(",,,,"),
-        vec![(String::new(), "String".to_owned()); 5]);
+        assert_eq!(super::parse_columns("key1,,key3").unwrap(),
+        vec![(None, "key1".to_owned(), "String".to_owned()),
+             (None, String::new(), "String".to_owned()),
+             (None, "key3".to_owned(), "String".to_owned())]);
+        assert_eq!(super::parse_columns(",,,,").unwrap(),
+        vec![(None, String::new(), "String".to_owned()); 5]);
+        assert_eq!(super::parse_columns("1:date:Date, Amount:price:Decimal").unwrap(),
+        vec![(Some("1".to_owned()), "date".to_owned(), "Date".to_owned()),
+             (Some("Amount".to_owned()), "price".to_owned(), "Decimal".to_owned())]);
+        assert!(super::parse_columns("a:b:c:d").is_err());
+        assert_eq!(super::parse_columns("1:time:Date(%Y-%m-%d %H:%M), 2:date:Date(%d,%m,%Y)").unwrap(),
+        vec![(Some("1".to_owned()), "time".to_owned(), "Date(%Y-%m-%d %H:%M)".to_owned()),
+             (Some("2".to_owned()), "date".to_owned(), "Date(%d,%m,%Y)".to_owned())]);
+        assert_eq!(super::parse_columns("price:Decimal(prefix='(', suffix=')'), Owner's:owner:String").unwrap(),
+        vec![(None, "price".to_owned(), "Decimal(prefix='(', suffix=')')".to_owned()),
+             (Some("Owner's".to_owned()), "owner".to_owned(), "String".to_owned())]);
+        assert_eq!(super::parse_columns("'Time: start, end':time:Time, 'a,b'").unwrap(),
+        vec![(Some("'Time: start, end'".to_owned()), "time".to_owned(), "Time".to_owned()),
+             (None, "'a,b'".to_owned(), "String".to_owned())]);
+    }
+
+    #[test]
+    fn field() {
+        use super::{Columns, Field};
+
+        let cols: Columns = "1:date:Date, 7:price:Decimal".parse().unwrap();
+        assert!(cols.is_selective());
+        let fields: Vec<_> = cols.iter().map(|col| col.field().cloned()).collect();
+        assert_eq!(fields, vec![Some(Field::Position(0)), Some(Field::Position(6))]);
+
+        let cols: Columns = "Posting Date:date:Date".parse().unwrap();
+        assert_eq!(cols.iter().next().unwrap().field(), Some(&Field::Header("Posting Date".to_owned())));
+
+        let cols: Columns = "date:Date, price:Decimal".parse().unwrap();
+        assert!(!cols.is_selective());
+        assert!(!cols.iter().any(|col| col.is_nullable()));
+
+        let cols: Columns = "1:fee:Decimal(group=',')?".parse().unwrap();
+        assert!(cols.iter().next().unwrap().is_nullable());
+
+        assert!("0:date:Date".parse::<Columns>().is_err());
+
+        let cols: Columns = "'2020':amount:Decimal".parse().unwrap();
+        assert_eq!(cols.iter().next().unwrap().field(), Some(&Field::Header("2020".to_owned())));
     }
 }
@@ -1,13 +1,23 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use log::warn;
+use once_cell::unsync::OnceCell;
 use eyre::Result;
-use crate::table::Table;
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use crate::table::{Table, CsvFormat, LoadStats};
 use crate::columns::Columns;
 use crate::record::{Record, Mapping};
 use crate::quotient::Quotient;
 use crate::app::App;
+use crate::expr::{Approx, SumEqual};
+use crate::Value;
 
 pub struct TableQuotient {
     columns: Columns,
+    format: CsvFormat,
+    header: Option<StringRecord>,
+    stats: LoadStats,
     quotient: Quotient<Record, Record, Mapping>,
 }
 
@@ -21,6 +31,9 @@ impl TableQuotient {
 
         TableQuotient {
             columns: table.columns().clone(),
+            format: table.format().clone(),
+            header: table.header().cloned(),
+            stats: table.stats(),
             quotient: quot,
         }
     }
@@ -29,41 +42,490 @@ impl TableQuotient {
         &self.columns
     }
 
+    /// Returns the dialect of the input files
+    pub fn format(&self) -> &CsvFormat {
+        &self.format
+    }
+
+    pub fn header(&self) -> Option<&StringRecord> {
+        self.header.as_ref()
+    }
+
+    /// Returns the counters of loading the table
+    pub fn stats(&self) -> LoadStats {
+        self.stats
+    }
+
     pub fn quotient(&self) -> &Quotient<Record, Record, Mapping> {
         &self.quotient
     }
 
+    /// Returns the number of records in all classes
+    pub fn num_records(&self) -> usize {
+        self.quotient.classes().map(|class| class.len()).sum()
+    }
+
     pub fn difference<'a>(&'a self, other: &'a Self) -> crate::quotient::Difference<'a, Record, Record, Mapping> {
         self.quotient.difference(&other.quotient)
     }
 }
 
+/// Records of a class split into matched records and surpluses
+///
+/// Records are matched in the order of their appearance.
+/// When sums of classes are compared, the numbers of matched records may differ between sides.
+#[derive(Debug,Clone,Copy)]
+pub struct Partition<'a> {
+    pub source_matched: &'a [Record],
+    pub target_matched: &'a [Record],
+    pub source_only: &'a [Record],
+    pub target_only: &'a [Record],
+}
+
+/// Statistics of one side of a comparison
+#[derive(Debug,Clone,PartialEq)]
+pub struct SideSummary {
+    pub stats: LoadStats,
+    /// Number of records in all classes
+    pub records: usize,
+    /// Number of equivalence classes
+    pub classes: usize,
+    /// Number of classes which are absent on the other side
+    pub only_classes: usize,
+    /// Number of records without counterparts
+    pub unmatched_records: usize,
+    /// Totals of decimal columns over records without counterparts
+    pub unmatched_totals: Vec<(String, Decimal)>,
+}
+
+impl SideSummary {
+    fn new(quot: &TableQuotient) -> Self {
+        let unmatched_totals = quot.columns().iter()
+            .filter(|col| matches!(col.tag(), crate::Tag::Decimal(_)))
+            .map(|col| (col.name().to_owned(), Decimal::new(0, 0)))
+            .collect();
+
+        SideSummary {
+            stats: quot.stats(),
+            records: quot.num_records(),
+            classes: quot.quotient().len(),
+            only_classes: 0,
+            unmatched_records: 0,
+            unmatched_totals,
+        }
+    }
+
+    /// Adds unmatched records to the totals, which are in the order of decimal columns
+    fn add_unmatched(&mut self, columns: &Columns, records: &[Record]) {
+        self.unmatched_records += records.len();
+        let indices = columns.iter()
+            .enumerate()
+            .filter(|(_, col)| matches!(col.tag(), crate::Tag::Decimal(_)))
+            .map(|(index, _)| index);
+        for ((_, total), index) in self.unmatched_totals.iter_mut().zip(indices) {
+            for record in records.iter() {
+                if let Value::Decimal(x) = record[index] {
+                    *total += x;
+                }
+            }
+        }
+    }
+}
+
+/// Statistics of a comparison
+#[derive(Debug,Clone,PartialEq)]
+pub struct Summary {
+    pub source: SideSummary,
+    pub target: SideSummary,
+    /// Number of classes whose records are all matched
+    pub matched_classes: usize,
+    /// Number of classes on both sides whose records are not all matched
+    pub count_mismatches: usize,
+    /// Number of pairs matched with non-zero differences
+    pub approximate_matches: usize,
+    /// Number of proposed many-to-one matches
+    pub subset_matches: usize,
+}
+
+/// Split of points into a key and compared values
+///
+/// Records with the same key and different compared values are reported as modified.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct KeySplit {
+    key_len: usize,
+    names: Vec<String>,
+}
+
+impl KeySplit {
+    /// `names` are labels of compared values which follow `key_len` key values in points
+    pub fn new(key_len: usize, names: Vec<String>) -> Self {
+        KeySplit { key_len, names }
+    }
+}
+
+/// A compared value which differs between a source record and a target record
+#[derive(Debug,Clone,PartialEq)]
+pub struct FieldChange<'a> {
+    pub name: &'a str,
+    pub source: &'a Value,
+    pub target: &'a Value,
+}
+
+/// A record-level change found with a key split
+#[derive(Debug,Clone,PartialEq)]
+pub enum Change<'a> {
+    /// A source record whose key is absent on the target
+    Removed {
+        key: &'a [Value],
+        record: &'a Record,
+    },
+    /// A target record whose key is absent on the source
+    Added {
+        key: &'a [Value],
+        record: &'a Record,
+    },
+    /// A pair of records with the same key and different compared values
+    Modified {
+        key: &'a [Value],
+        source: &'a Record,
+        target: &'a Record,
+        fields: Vec<FieldChange<'a>>,
+    },
+}
+
+/// Conditions of matching records besides the predicate
+#[derive(Debug,Clone,Default)]
+pub struct Matching {
+    pub key_split: Option<KeySplit>,
+    pub approx: Vec<Approx>,
+    pub sum_equal: Option<SumEqual>,
+    pub subset_search: Option<SubsetSearch>,
+}
+
+/// Search for sets of unmatched records whose sum equals an unmatched record on the other side
+#[derive(Debug,Clone)]
+pub struct SubsetSearch {
+    sum_equal: SumEqual,
+    within: Vec<Approx>,
+    max_size: usize,
+    time_limit: Duration,
+}
+
+impl SubsetSearch {
+    pub fn new(sum_equal: SumEqual) -> Self {
+        SubsetSearch {
+            sum_equal,
+            within: Vec::new(),
+            max_size: 3,
+            time_limit: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets conditions which each member of a set satisfies with the counterpart
+    pub fn within(self, within: Vec<Approx>) -> Self {
+        SubsetSearch { within, ..self }
+    }
+
+    /// Sets the maximum number of records in a set
+    pub fn max_size(self, max_size: usize) -> Self {
+        SubsetSearch { max_size, ..self }
+    }
+
+    /// Sets the time budget of the whole search
+    pub fn time_limit(self, time_limit: Duration) -> Self {
+        SubsetSearch { time_limit, ..self }
+    }
+
+    /// Finds a set of source records whose sum equals the target record and returns their indices
+    fn find_sources(&self, src: &[Option<&Record>], target: &Record, deadline: Instant) -> Option<Vec<usize>> {
+        let goal = self.sum_equal.target_value(target)?;
+        let values: Vec<(usize, Decimal)> = src.iter()
+            .enumerate()
+            .filter_map(|(i, r)| r.map(|r| (i, r)))
+            .filter(|(_, r)| self.within.iter().all(|a| a.delta(r, target).is_some()))
+            .filter_map(|(i, r)| self.sum_equal.source_value(r).map(|x| (i, x)))
+            .collect();
+        find_subset(&values, goal, self.max_size, deadline)
+    }
+
+    /// Finds a set of target records whose sum equals the source record and returns their indices
+    fn find_targets(&self, tgt: &[Option<&Record>], source: &Record, deadline: Instant) -> Option<Vec<usize>> {
+        let goal = self.sum_equal.source_value(source)?;
+        let values: Vec<(usize, Decimal)> = tgt.iter()
+            .enumerate()
+            .filter_map(|(j, r)| r.map(|r| (j, r)))
+            .filter(|(_, r)| self.within.iter().all(|a| a.delta(source, r).is_some()))
+            .filter_map(|(j, r)| self.sum_equal.target_value(r).map(|x| (j, x)))
+            .collect();
+        find_subset(&values, goal, self.max_size, deadline)
+    }
+}
+
+/// Records proposed to match as many to one
+#[derive(Debug,Clone,PartialEq)]
+pub struct SubsetMatch<'a> {
+    pub source: Vec<&'a Record>,
+    pub target: Vec<&'a Record>,
+}
+
+/// Finds two to `max_size` values whose sum is `goal` and returns their indices
+///
+/// Returns `None` if no set is found or the deadline has passed.
+fn find_subset(values: &[(usize, Decimal)], goal: Decimal, max_size: usize, deadline: Instant) -> Option<Vec<usize>> {
+    fn search(values: &[(usize, Decimal)], goal: Decimal, size: usize, chosen: &mut Vec<usize>, deadline: Instant) -> Option<bool> {
+        if chosen.len() == size {
+            return Some(goal == Decimal::new(0, 0));
+        }
+        if Instant::now() > deadline {
+            return None;
+        }
+        for (k, &(index, value)) in values.iter().enumerate() {
+            chosen.push(index);
+            if search(&values[k+1..], goal - value, size, chosen, deadline)? {
+                return Some(true);
+            }
+            chosen.pop();
+        }
+        Some(false)
+    }
+
+    for size in 2..=max_size {
+        let mut chosen = Vec::new();
+        if search(values, goal, size, &mut chosen, deadline)? {
+            return Some(chosen);
+        }
+    }
+    None
+}
+
+/// Sums of the records of a class on both sides
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct GroupSums {
+    pub source: Decimal,
+    pub target: Decimal,
+}
+
+/// A pair of records matched by approximate equalities with non-zero differences
+#[derive(Debug,Clone)]
+pub struct ApproxMatch<'a> {
+    pub point: &'a Record,
+    pub source: &'a Record,
+    pub target: &'a Record,
+    /// Differences of target values from source values
+    pub deltas: Vec<(&'a Approx, Decimal)>,
+}
+
 pub struct TableComparator {
     source: TableQuotient,
     target: TableQuotient,
+    null_equals_null: bool,
+    key_split: Option<KeySplit>,
+    approx: Vec<Approx>,
+    /// Numbers of records paired by approximate equalities for each point
+    matched: HashMap<Record, usize>,
+    sum_equal: Option<SumEqual>,
+    subset_search: Option<SubsetSearch>,
+    /// Sets of source and target records found by the subset search on first use
+    subset_matches: OnceCell<Vec<(Vec<Record>, Vec<Record>)>>,
     empty_vec: Vec<Record>,
 }
 
+/// Pairs records satisfying all approximate equalities, closest pairs first
+///
+/// Among equally close pairs, records with fewer candidates are paired first.
+/// Returns pairs of indices in ascending order of source indices.
+fn pair_closest(approx: &[Approx], src: &[Record], tgt: &[Record]) -> Vec<(usize, usize)> {
+    let mut candidates = Vec::new();
+    let mut src_candidates = vec![0; src.len()];
+    let mut tgt_candidates = vec![0; tgt.len()];
+    for (i, s) in src.iter().enumerate() {
+        for (j, t) in tgt.iter().enumerate() {
+            let distances: Option<Vec<Decimal>> = approx.iter()
+                .map(|a| a.delta(s, t).map(|d| d.abs()))
+                .collect();
+            if let Some(distances) = distances {
+                candidates.push((distances, i, j));
+                src_candidates[i] += 1;
+                tgt_candidates[j] += 1;
+            }
+        }
+    }
+    let key = |i: usize, j: usize| (src_candidates[i] + tgt_candidates[j], i, j);
+    candidates.sort_by(|(da, ia, ja), (db, ib, jb)| da.cmp(db).then_with(|| key(*ia, *ja).cmp(&key(*ib, *jb))));
+
+    let mut src_used = vec![false; src.len()];
+    let mut tgt_used = vec![false; tgt.len()];
+    let mut pairs = Vec::new();
+    for (_, i, j) in candidates {
+        if !src_used[i] && !tgt_used[j] {
+            src_used[i] = true;
+            tgt_used[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs.sort();
+
+    pairs
+}
+
+/// Moves records at `indices` to the front in the given order
+fn move_to_front<I: IntoIterator<Item=usize>>(class: &mut Vec<Record>, indices: I) {
+    let mut rest: Vec<Option<Record>> = class.drain(..).map(Some).collect();
+    for index in indices {
+        class.push(rest[index].take().unwrap());
+    }
+    class.extend(rest.into_iter().flatten());
+}
+
 impl TableComparator {
+    pub fn new(source: TableQuotient, target: TableQuotient, null_equals_null: bool) -> Self {
+        Self {
+            source,
+            target,
+            null_equals_null,
+            key_split: None,
+            approx: Vec::new(),
+            matched: HashMap::new(),
+            sum_equal: None,
+            subset_search: None,
+            subset_matches: OnceCell::new(),
+            empty_vec: Vec::new(),
+        }
+    }
+
+    /// Matches records of each point by approximate equalities
+    ///
+    /// Paired records are moved to the front of their classes.
+    pub fn with_approx(mut self, approx: Vec<Approx>) -> Self {
+        if approx.is_empty() {
+            return self;
+        }
+
+        let points: Vec<Record> = self.points().into_iter().cloned().collect();
+        for pt in points {
+            if self.is_unmatchable(&pt) {
+                continue;
+            }
+            let (src, tgt) = match (self.source.quotient.get_mut(&pt), self.target.quotient.get_mut(&pt)) {
+                (Some(src), Some(tgt)) => (src, tgt),
+                _ => continue,
+            };
+            let pairs = pair_closest(&approx, src, tgt);
+            move_to_front(src, pairs.iter().map(|&(i, _)| i));
+            move_to_front(tgt, pairs.iter().map(|&(_, j)| j));
+            self.matched.insert(pt, pairs.len());
+        }
+
+        Self {
+            approx,
+            subset_matches: OnceCell::new(),
+            ..self
+        }
+    }
+
+    pub fn with_key_split(self, key_split: KeySplit) -> Self {
+        Self {
+            key_split: Some(key_split),
+            ..self
+        }
+    }
+
+    /// Compares sums of classes instead of the numbers of records
+    ///
+    /// All records of a class are matched if the sums are equal.
+    pub fn with_sum_equal(self, sum_equal: SumEqual) -> Self {
+        Self {
+            sum_equal: Some(sum_equal),
+            subset_matches: OnceCell::new(),
+            ..self
+        }
+    }
+
+    /// Proposes many-to-one matches among unmatched records after comparison
+    ///
+    /// The search runs once when the matches are first used.
+    pub fn with_subset_search(self, subset_search: SubsetSearch) -> Self {
+        Self {
+            subset_search: Some(subset_search),
+            subset_matches: OnceCell::new(),
+            ..self
+        }
+    }
+
     pub fn from_app(app: &App) -> Result<Self> {
-        let (source, target) = app.to_quotients()?;
+        let (source, target, matching) = app.to_quotients()?;
 
-        Ok(Self {
-            source: source,
-            target: target,
-            empty_vec: Vec::new(),
+        let mut comp = Self::new(source, target, app.null_equals_null());
+        if let Some(key_split) = matching.key_split {
+            comp = comp.with_key_split(key_split);
+        }
+        if let Some(sum_equal) = matching.sum_equal {
+            comp = comp.with_sum_equal(sum_equal);
+        }
+        comp = comp.with_approx(matching.approx);
+        if let Some(subset_search) = matching.subset_search {
+            comp = comp.with_subset_search(subset_search);
+        }
+        Ok(comp)
+    }
+
+    pub fn key_split(&self) -> Option<&KeySplit> {
+        self.key_split.as_ref()
+    }
+
+    pub fn sum_equal(&self) -> Option<&SumEqual> {
+        self.sum_equal.as_ref()
+    }
+
+    /// Returns the sums of a class if sums are compared
+    pub fn group_sums(&self, pt: &Record) -> Option<GroupSums> {
+        let sum_equal = self.sum_equal.as_ref()?;
+        let (src, tgt) = self.get_records(pt);
+        Some(GroupSums {
+            source: sum_equal.source_sum(src),
+            target: sum_equal.target_sum(tgt),
         })
     }
 
-    pub fn different_points<'a>(&'a self) -> Vec<&'a Record> {
+    pub fn source(&self) -> &TableQuotient {
+        &self.source
+    }
+
+    pub fn target(&self) -> &TableQuotient {
+        &self.target
+    }
+
+    /// Returns `true` if the tables have no different points
+    pub fn is_same(&self) -> bool {
+        self.different_points().is_empty()
+    }
+
+    pub fn different_points(&self) -> Vec<&Record> {
+        if !self.approx.is_empty() || self.sum_equal.is_some() {
+            return self.points()
+                .into_iter()
+                .filter(|pt| {
+                    let part = self.partition(pt);
+                    !part.source_only.is_empty() || !part.target_only.is_empty()
+                })
+                .collect();
+        }
+
         let mut points = HashSet::new();
 
-        for pt in self.source.difference(&self.target) {
+        for (pt, _) in self.source.difference(&self.target) {
             points.insert(pt);
         }
-        for pt in self.target.difference(&self.source) {
+        for (pt, _) in self.target.difference(&self.source) {
             points.insert(pt);
         }
+        let reprs = self.source.quotient().representatives()
+            .chain(self.target.quotient().representatives());
+        for pt in reprs {
+            if self.is_unmatchable(pt) {
+                points.insert(pt);
+            }
+        }
 
         let mut points: Vec<&Record> = points.drain().collect();
         points.sort();
@@ -71,6 +533,205 @@ impl TableComparator {
         points
     }
 
+    /// Returns `true` if records of the point never match
+    fn is_unmatchable(&self, pt: &Record) -> bool {
+        !self.null_equals_null && pt.contains(&Value::Null)
+    }
+
+    pub fn partition(&self, pt: &Record) -> Partition<'_> {
+        let (src, tgt) = self.get_records(pt);
+        let (src_matched, tgt_matched) = if self.is_unmatchable(pt) {
+            (0, 0)
+        } else if let Some(sums) = self.group_sums(pt) {
+            if sums.source == sums.target && !src.is_empty() && !tgt.is_empty() {
+                (src.len(), tgt.len())
+            } else {
+                (0, 0)
+            }
+        } else if !self.approx.is_empty() {
+            let matched = self.matched.get(pt).copied().unwrap_or(0);
+            (matched, matched)
+        } else {
+            let matched = src.len().min(tgt.len());
+            (matched, matched)
+        };
+
+        Partition {
+            source_matched: &src[..src_matched],
+            target_matched: &tgt[..tgt_matched],
+            source_only: &src[src_matched..],
+            target_only: &tgt[tgt_matched..],
+        }
+    }
+
+    /// Returns all points of both tables in ascending order
+    pub fn points(&self) -> Vec<&Record> {
+        let mut points: Vec<&Record> = self.source.quotient().representatives()
+            .chain(self.target.quotient().representatives())
+            .collect::<HashSet<&Record>>()
+            .into_iter()
+            .collect();
+        points.sort();
+
+        points
+    }
+
+    /// Returns pairs matched with non-zero differences in ascending order of points
+    pub fn approximate_matches(&self) -> Vec<ApproxMatch<'_>> {
+        let mut matches = Vec::new();
+        for point in self.points() {
+            let part = self.partition(point);
+            for (source, target) in part.source_matched.iter().zip(part.target_matched.iter()) {
+                let deltas: Vec<(&Approx, Decimal)> = self.approx.iter()
+                    .filter_map(|a| a.delta(source, target).map(|d| (a, d)))
+                    .filter(|(_, d)| *d != Decimal::new(0, 0))
+                    .collect();
+                if !deltas.is_empty() {
+                    matches.push(ApproxMatch { point, source, target, deltas });
+                }
+            }
+        }
+
+        matches
+    }
+
+    /// Returns sets of unmatched records whose sums equal unmatched records on the other side
+    ///
+    /// Returns an empty vector if no subset search is set.
+    pub fn subset_matches(&self) -> Vec<SubsetMatch<'_>> {
+        let found = self.subset_matches.get_or_init(|| match self.subset_search {
+            Some(ref search) => self.search_subsets(search)
+                .into_iter()
+                .map(|m| (m.source.into_iter().cloned().collect(), m.target.into_iter().cloned().collect()))
+                .collect(),
+            None => Vec::new(),
+        });
+        found.iter()
+            .map(|(source, target)| SubsetMatch {
+                source: source.iter().collect(),
+                target: target.iter().collect(),
+            })
+            .collect()
+    }
+
+    /// Searches sets of unmatched records whose sums equal unmatched records on the other side
+    ///
+    /// Sets of source records are searched first, and each record is used in one match at most.
+    fn search_subsets(&self, search: &SubsetSearch) -> Vec<SubsetMatch<'_>> {
+        let deadline = Instant::now() + search.time_limit;
+
+        let points = self.different_points();
+        let mut src: Vec<Option<&Record>> = points.iter()
+            .flat_map(|pt| self.partition(pt).source_only)
+            .map(Some)
+            .collect();
+        let mut tgt: Vec<Option<&Record>> = points.iter()
+            .flat_map(|pt| self.partition(pt).target_only)
+            .map(Some)
+            .collect();
+
+        let mut matches = Vec::new();
+        for slot in tgt.iter_mut() {
+            let t = match *slot {
+                Some(t) => t,
+                None => continue,
+            };
+            if let Some(indices) = search.find_sources(&src, t, deadline) {
+                let source = indices.iter().map(|&i| src[i].take().unwrap()).collect();
+                *slot = None;
+                matches.push(SubsetMatch { source, target: vec![t] });
+            }
+        }
+        for slot in src.iter_mut() {
+            let s = match *slot {
+                Some(s) => s,
+                None => continue,
+            };
+            if let Some(indices) = search.find_targets(&tgt, s, deadline) {
+                let target = indices.iter().map(|&j| tgt[j].take().unwrap()).collect();
+                *slot = None;
+                matches.push(SubsetMatch { source: vec![s], target });
+            }
+        }
+        if Instant::now() > deadline {
+            warn!("Subset search stopped after {:?}", search.time_limit);
+        }
+
+        matches
+    }
+
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary {
+            source: SideSummary::new(&self.source),
+            target: SideSummary::new(&self.target),
+            matched_classes: 0,
+            count_mismatches: 0,
+            approximate_matches: self.approximate_matches().len(),
+            subset_matches: self.subset_matches().len(),
+        };
+
+        for pt in self.points() {
+            let part = self.partition(pt);
+            let (src, tgt) = self.get_records(pt);
+            if tgt.is_empty() {
+                summary.source.only_classes += 1;
+            } else if src.is_empty() {
+                summary.target.only_classes += 1;
+            } else if part.source_only.is_empty() && part.target_only.is_empty() {
+                summary.matched_classes += 1;
+            } else {
+                summary.count_mismatches += 1;
+            }
+            summary.source.add_unmatched(self.source.columns(), part.source_only);
+            summary.target.add_unmatched(self.target.columns(), part.target_only);
+        }
+
+        summary
+    }
+
+    /// Pairs surplus records by keys and returns changes in ascending order of keys
+    ///
+    /// Surplus records with the same key are paired in the order of their appearance.
+    /// Returns an empty vector if no key split is set.
+    pub fn changes(&self) -> Vec<Change<'_>> {
+        type Surpluses<'a> = (Vec<(&'a Record, &'a Record)>, Vec<(&'a Record, &'a Record)>);
+
+        let split = match self.key_split {
+            Some(ref split) => split,
+            None => return Vec::new(),
+        };
+
+        let mut surpluses: BTreeMap<&[Value], Surpluses> = BTreeMap::new();
+        for pt in self.different_points() {
+            let part = self.partition(pt);
+            let entry = surpluses.entry(&pt[..split.key_len]).or_default();
+            entry.0.extend(part.source_only.iter().map(|r| (pt, r)));
+            entry.1.extend(part.target_only.iter().map(|r| (pt, r)));
+        }
+
+        let mut changes = Vec::new();
+        for (key, (src, tgt)) in surpluses {
+            for (&(spt, source), &(tpt, target)) in src.iter().zip(tgt.iter()) {
+                let fields = split.names.iter()
+                    .enumerate()
+                    .map(|(i, name)| (name, &spt[split.key_len + i], &tpt[split.key_len + i]))
+                    .filter(|(_, s, t)| s != t || (!self.null_equals_null && (**s == Value::Null || **t == Value::Null)))
+                    .map(|(name, source, target)| FieldChange { name, source, target })
+                    .collect();
+                changes.push(Change::Modified { key, source, target, fields });
+            }
+            let paired = src.len().min(tgt.len());
+            for &(_, record) in src[paired..].iter() {
+                changes.push(Change::Removed { key, record });
+            }
+            for &(_, record) in tgt[paired..].iter() {
+                changes.push(Change::Added { key, record });
+            }
+        }
+
+        changes
+    }
+
     pub fn get_records(&self, pt: &Record) -> (&[Record], &[Record]) {
         let src_class = match self.source.quotient().get(pt) {
             Some(class) => class,
@@ -83,3 +744,179 @@ impl TableComparator {
         (src_class, tgt_class)
     }
 }
+
+/// Reads a source and a target file with column specifiers and compares them by `predicate`
+#[cfg(test)]
+pub(crate) fn compare_files(source: (&str, &str), target: (&str, &str), predicate: &str) -> TableComparator {
+    let read = |path: &str, columns: &Columns| crate::table::TableBuilder::new()
+        .columns(columns.clone())
+        .from_path(&[path])
+        .unwrap();
+    let rcols: Columns = source.1.parse().unwrap();
+    let lcols: Columns = target.1.parse().unwrap();
+    let (rterms, lterms) = crate::expr::parse_equal(predicate, &rcols, &lcols).unwrap();
+    TableComparator::new(
+        TableQuotient::new(&read(source.0, &rcols), &rterms.into()),
+        TableQuotient::new(&read(target.0, &lcols), &lterms.into()),
+        false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POSTING_COLUMNS: &str = "date:Date,description,account,unit,price:Decimal";
+    const LEDGER_COLUMNS: &str = "date:Date,description,price:Decimal";
+    const BANK_COLUMNS: &str = "date:Date,description,amount:Decimal";
+
+    #[test]
+    fn summary() {
+        let comp = compare_files(("examples/base.csv", POSTING_COLUMNS), ("examples/delta.csv", POSTING_COLUMNS),
+            "(date, price) = (date, price)");
+        let summary = comp.summary();
+
+        assert_eq!(summary.source.stats.read, 4);
+        assert_eq!(summary.source.classes, 4);
+        assert_eq!(summary.matched_classes, 3);
+        assert_eq!(summary.source.only_classes, 1);
+        assert_eq!(summary.target.only_classes, 1);
+        assert_eq!(summary.count_mismatches, 0);
+        assert_eq!(summary.source.unmatched_totals, vec![("price".to_owned(), (-1000).into())]);
+        assert_eq!(summary.target.unmatched_totals, vec![("price".to_owned(), (-4500).into())]);
+    }
+
+    #[test]
+    fn closest_pairs() {
+        let columns: Columns = "price:Decimal".parse().unwrap();
+        let approx = crate::expr::parse_approx("price ~ price within 1", &columns, &columns).unwrap();
+        let records = |prices: &[i64]| -> Vec<Record> {
+            prices.iter().map(|&p| Record::from(vec![Value::Decimal(p.into())])).collect()
+        };
+
+        // 102 is as close to 101 as to 103, but 101 is the only candidate of 100
+        let src = records(&[102, 100]);
+        let tgt = records(&[101, 103, 200]);
+        assert_eq!(pair_closest(&[approx], &src, &tgt), vec![(0, 1), (1, 0)]);
+
+        let mut class = records(&[1, 2, 3, 4]);
+        move_to_front(&mut class, vec![2, 0]);
+        assert_eq!(class, records(&[3, 1, 2, 4]));
+    }
+
+    #[test]
+    fn changes() {
+        let comp = compare_files(("examples/base.csv", POSTING_COLUMNS), ("examples/revised.csv", POSTING_COLUMNS),
+            "(date, description, price) = (date, description, price)")
+            .with_key_split(KeySplit::new(2, vec!["price".to_owned()]));
+
+        let changes = comp.changes();
+        assert_eq!(changes.len(), 3);
+        match &changes[0] {
+            Change::Modified { key, source, target, fields } => {
+                assert_eq!(key[1], Value::String("Diner".to_owned()));
+                assert_eq!(source[4], Value::Decimal((-5000).into()));
+                assert_eq!(target[4], Value::Decimal((-5500).into()));
+                assert_eq!(fields.len(), 1);
+                assert_eq!(fields[0].name, "price");
+            },
+            other => panic!("unexpected change: {:?}", other),
+        }
+        assert!(matches!(&changes[1], Change::Removed { key, .. } if key[1] == Value::String("Lunch".to_owned())));
+        assert!(matches!(&changes[2], Change::Added { key, .. } if key[1] == Value::String("Adobe".to_owned())));
+    }
+
+    #[test]
+    fn approximate_matches() {
+        let comp = compare_files(("examples/ledger_usd.csv", LEDGER_COLUMNS), ("examples/card_usd.csv", BANK_COLUMNS), "(date) = (date)");
+        let approx = crate::expr::parse_approx("price ~ amount within 0.01", comp.source().columns(), comp.target().columns()).unwrap();
+        let comp = comp.with_approx(vec![approx]);
+
+        let matches = comp.approximate_matches();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].source[1], Value::String("Book".to_owned()));
+        assert_eq!(matches[0].target[1], Value::String("BOOKSTORE".to_owned()));
+        assert_eq!(matches[0].deltas.len(), 1);
+        assert_eq!(matches[0].deltas[0].1, "-0.01".parse().unwrap());
+
+        let points: Vec<String> = comp.different_points().iter().map(|p| p.to_string()).collect();
+        assert_eq!(points, vec!["2020-09-10"]);
+        let part = comp.partition(comp.different_points()[0]);
+        assert_eq!((part.source_only.len(), part.target_only.len()), (1, 1));
+    }
+
+    #[test]
+    fn group_sums() {
+        let comp = compare_files(("examples/postings.csv", POSTING_COLUMNS), ("examples/history.csv", LEDGER_COLUMNS), "(date) = (date)");
+        let sum_equal = crate::expr::parse_sum_equal("-price = price", comp.source().columns(), comp.target().columns()).unwrap();
+        let comp = comp.with_sum_equal(sum_equal);
+
+        let points: Vec<String> = comp.different_points().iter().map(|p| p.to_string()).collect();
+        assert_eq!(points, vec!["2020-09-11", "2020-09-16", "2020-09-18"]);
+        let sums = comp.group_sums(comp.different_points()[0]).unwrap();
+        assert_eq!((sums.source, sums.target), ("200".parse().unwrap(), "100".parse().unwrap()));
+        assert!(comp.points().iter().all(|pt| comp.group_sums(pt).is_some()));
+    }
+
+    #[test]
+    fn subset_matches() {
+        let comp = compare_files(("examples/split_ledger.csv", LEDGER_COLUMNS), ("examples/split_bank.csv", BANK_COLUMNS), "(date, price) = (date, amount)");
+        let sum_equal = crate::expr::parse_sum_equal("price = amount", comp.source().columns(), comp.target().columns()).unwrap();
+        let within = crate::expr::parse_approx("date ~ date within 0 days", comp.source().columns(), comp.target().columns()).unwrap();
+        let comp = comp.with_subset_search(SubsetSearch::new(sum_equal).within(vec![within]));
+
+        let matches = comp.subset_matches();
+        assert_eq!(matches.len(), 1);
+        let descriptions: Vec<String> = matches[0].source.iter().map(|r| r[1].to_string()).collect();
+        assert_eq!(descriptions, vec!["Rent part 1", "Rent part 2"]);
+        assert_eq!(matches[0].target.len(), 1);
+        assert_eq!(comp.summary().subset_matches, 1);
+    }
+
+    #[test]
+    fn subset_search_after_approx() {
+        let comp = compare_files(("examples/split_ledger.csv", LEDGER_COLUMNS), ("examples/split_bank.csv", BANK_COLUMNS), "(date) = (date)");
+        let sum_equal = crate::expr::parse_sum_equal("price = amount", comp.source().columns(), comp.target().columns()).unwrap();
+        let approx = crate::expr::parse_approx("price ~ amount within 0", comp.source().columns(), comp.target().columns()).unwrap();
+        let comp = comp.with_subset_search(SubsetSearch::new(sum_equal))
+            .with_approx(vec![approx]);
+
+        let matches = comp.subset_matches();
+        let sizes: Vec<(usize, usize)> = matches.iter().map(|m| (m.source.len(), m.target.len())).collect();
+        assert_eq!(sizes, vec![(2, 1), (2, 1)]);
+        let descriptions: Vec<String> = matches[0].source.iter().map(|r| r[1].to_string()).collect();
+        assert_eq!(descriptions, vec!["Rent part 1", "Rent part 2"]);
+    }
+
+    #[test]
+    fn unmatched_totals() {
+        let columns: Columns = "amount:Decimal, memo, amount:Decimal".parse().unwrap();
+        let mut summary = SideSummary {
+            stats: LoadStats::default(),
+            records: 0,
+            classes: 0,
+            only_classes: 0,
+            unmatched_records: 0,
+            unmatched_totals: vec![("amount".to_owned(), 0.into()), ("amount".to_owned(), 0.into())],
+        };
+        let record = Record::from(vec![Value::Decimal(100.into()), Value::Null, Value::Decimal((-3).into())]);
+        summary.add_unmatched(&columns, &[record.clone(), record]);
+        assert_eq!(summary.unmatched_records, 2);
+        assert_eq!(summary.unmatched_totals,
+            vec![("amount".to_owned(), 200.into()), ("amount".to_owned(), (-6).into())]);
+    }
+
+    #[test]
+    fn subset() {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let values: Vec<(usize, Decimal)> = [500, 300, 200, 100].iter()
+            .enumerate()
+            .map(|(i, &x)| (i, x.into()))
+            .collect();
+        assert_eq!(find_subset(&values, 800.into(), 3, deadline), Some(vec![0, 1]));
+        assert_eq!(find_subset(&values, 600.into(), 3, deadline), Some(vec![0, 3]));
+        assert_eq!(find_subset(&values, 1000.into(), 3, deadline), Some(vec![0, 1, 2]));
+        assert_eq!(find_subset(&values, 1000.into(), 2, deadline), None);
+        assert_eq!(find_subset(&values, 500.into(), 3, deadline), Some(vec![1, 2]));
+        assert_eq!(find_subset(&values, 800.into(), 3, Instant::now() - Duration::from_secs(1)), None);
+    }
+}
@@ -0,0 +1,233 @@
+use std::io::{Read, Cursor};
+use encoding_rs::{UTF_8, UTF_16LE, UTF_16BE, SHIFT_JIS, EUC_JP};
+use thiserror::Error;
+use log::{debug, warn};
+
+#[derive(Error,Debug)]
+pub enum ParseError {
+    #[error("Unknown encoding: {0}")]
+    UnknownEncoding(String),
+}
+
+#[derive(Error,Debug)]
+pub enum DecodeError {
+    #[error("Failed to read an input")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to detect the character encoding")]
+    Undetected,
+}
+
+/// Character encoding of an input
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Encoding {
+    /// Detect an encoding from a byte order mark or the contents
+    Auto,
+    Label(&'static encoding_rs::Encoding),
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = self::ParseError;
+
+    /// Parses `auto` or a label defined in the WHATWG Encoding Standard
+    fn from_str(s: &str) -> Result<Encoding, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("auto") {
+            Ok(Encoding::Auto)
+        } else if s.eq_ignore_ascii_case("cp932") {
+            Ok(Encoding::Label(SHIFT_JIS))
+        } else {
+            encoding_rs::Encoding::for_label(s.as_bytes())
+                .map(Encoding::Label)
+                .ok_or_else(|| ParseError::UnknownEncoding(s.to_owned()))
+        }
+    }
+}
+
+impl Encoding {
+    /// Reads all bytes from `rdr` and converts them into a UTF-8 string
+    ///
+    /// Malformed sequences are replaced with U+FFFD.
+    pub fn decode<R: Read>(&self, mut rdr: R) -> Result<String, DecodeError> {
+        let mut bytes = Vec::new();
+        rdr.read_to_end(&mut bytes)?;
+
+        let encoding = match self {
+            Encoding::Auto => detect(&bytes).ok_or(DecodeError::Undetected)?,
+            Encoding::Label(encoding) => encoding,
+        };
+        debug!("Decode an input as {}", encoding.name());
+
+        let (text, had_errors) = encoding.decode_with_bom_removal(&bytes);
+        if had_errors {
+            warn!("Malformed sequences for {} are replaced", encoding.name());
+        }
+
+        Ok(text.into_owned())
+    }
+}
+
+/// Returns a reader which skips a UTF-8 byte order mark at the beginning of `rdr`
+pub fn skip_utf8_bom<R: Read>(mut rdr: R) -> std::io::Result<impl Read> {
+    let mut head = Vec::with_capacity(3);
+    (&mut rdr).take(3).read_to_end(&mut head)?;
+    if head == b"\xEF\xBB\xBF" {
+        head.clear();
+    }
+    Ok(Cursor::new(head).chain(rdr))
+}
+
+/// Guesses the encoding of `bytes`
+///
+/// A byte order mark takes precedence. Otherwise UTF-16 without BOM, UTF-8,
+/// EUC-JP and Shift_JIS are tried in this order.
+/// UTF-16 comes first because its NUL bytes are also valid in UTF-8.
+/// If a text is valid in both EUC-JP and Shift_JIS, the one with fewer half-width katakana is chosen.
+pub fn detect(bytes: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    if let Some((encoding, _)) = encoding_rs::Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+
+    if let Some(encoding) = detect_utf16(bytes) {
+        return Some(encoding);
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        return Some(UTF_8);
+    }
+
+    let euc = EUC_JP.decode_without_bom_handling_and_without_replacement(bytes);
+    let sjis = SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes);
+    match (euc, sjis) {
+        (Some(euc), Some(sjis)) => {
+            if count_halfwidth_katakana(&euc) < count_halfwidth_katakana(&sjis) {
+                Some(EUC_JP)
+            } else {
+                Some(SHIFT_JIS)
+            }
+        },
+        (Some(_), None) => Some(EUC_JP),
+        (None, Some(_)) => Some(SHIFT_JIS),
+        (None, None) => None,
+    }
+}
+
+/// Detects UTF-16 from line breaks, or from NUL bytes which appear in ASCII characters
+///
+/// Line breaks find texts mostly written in non-ASCII characters,
+/// since a line break decoded in the wrong byte order is not a line break.
+fn detect_utf16(bytes: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    if bytes.len() < 2 || bytes.len() % 2 == 1 {
+        return None;
+    }
+
+    for &encoding in &[UTF_16LE, UTF_16BE] {
+        if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+            let is_text = text.chars().all(|c| !c.is_control() || c == '\t' || c == '\r' || c == '\n');
+            if is_text && text.contains('\n') {
+                return Some(encoding);
+            }
+        }
+    }
+
+    let even = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    let threshold = bytes.len() / 4;
+
+    if odd > threshold && even < odd / 4 {
+        Some(UTF_16LE)
+    } else if even > threshold && odd < even / 4 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+fn count_halfwidth_katakana(s: &str) -> usize {
+    s.chars()
+        .filter(|c| ('\u{FF61}'..='\u{FF9F}').contains(c))
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("auto".parse::<Encoding>().unwrap(), Encoding::Auto);
+        assert_eq!("Shift_JIS".parse::<Encoding>().unwrap(), Encoding::Label(SHIFT_JIS));
+        assert_eq!("cp932".parse::<Encoding>().unwrap(), Encoding::Label(SHIFT_JIS));
+        assert_eq!("euc-jp".parse::<Encoding>().unwrap(), Encoding::Label(EUC_JP));
+        assert!("unknown".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn detect_encodings() {
+        let text = "2020年09月12日,昼食,-1000\n";
+
+        let (sjis, _, _) = SHIFT_JIS.encode(text);
+        assert_eq!(detect(&sjis), Some(SHIFT_JIS));
+
+        let (euc, _, _) = EUC_JP.encode(text);
+        assert_eq!(detect(&euc), Some(EUC_JP));
+
+        assert_eq!(detect(text.as_bytes()), Some(UTF_8));
+
+        let mut utf16: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect();
+        assert_eq!(detect(&utf16), Some(UTF_16LE));
+        utf16.splice(0..0, vec![0xFF, 0xFE]);
+        assert_eq!(Encoding::Auto.decode(&utf16[..]).unwrap(), text);
+
+        let utf16be: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes().to_vec()).collect();
+        assert_eq!(detect(&utf16be), Some(UTF_16BE));
+    }
+
+    #[test]
+    fn detect_ascii_utf16() {
+        let text = "a,1\nb,2\n";
+
+        let utf16le: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect();
+        assert_eq!(detect(&utf16le), Some(UTF_16LE));
+        assert_eq!(Encoding::Auto.decode(&utf16le[..]).unwrap(), text);
+
+        let utf16be: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes().to_vec()).collect();
+        assert_eq!(detect(&utf16be), Some(UTF_16BE));
+        assert_eq!(Encoding::Auto.decode(&utf16be[..]).unwrap(), text);
+
+        assert_eq!(detect(text.as_bytes()), Some(UTF_8));
+    }
+
+    #[test]
+    fn detect_non_ascii_utf16() {
+        let text = "日付,摘要,金額\n九月十二日,昼食,千円\n";
+
+        let utf16le: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect();
+        assert_eq!(detect(&utf16le), Some(UTF_16LE));
+        assert_eq!(Encoding::Auto.decode(&utf16le[..]).unwrap(), text);
+
+        let utf16be: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes().to_vec()).collect();
+        assert_eq!(detect(&utf16be), Some(UTF_16BE));
+        assert_eq!(Encoding::Auto.decode(&utf16be[..]).unwrap(), text);
+
+        assert_eq!(detect(text.as_bytes()), Some(UTF_8));
+    }
+
+    #[test]
+    fn skip_bom() {
+        let mut text = String::new();
+        skip_utf8_bom("\u{FEFF}date,price\n".as_bytes()).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "date,price\n");
+
+        let mut text = String::new();
+        skip_utf8_bom("da".as_bytes()).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "da");
+    }
+
+    #[test]
+    fn decode() {
+        let text = "2020年09月12日,昼食,-1000\n";
+        let (sjis, _, _) = SHIFT_JIS.encode(text);
+        assert_eq!(Encoding::Label(SHIFT_JIS).decode(&sjis[..]).unwrap(), text);
+        assert_eq!(Encoding::Auto.decode(&sjis[..]).unwrap(), text);
+    }
+}
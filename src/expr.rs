@@ -1,145 +1,1358 @@
 use crate::{Value, Tag};
 use crate::columns::Columns;
-use thiserror::Error;
+use crate::record::Record;
+use crate::parser::{Parser, Expr, ExprKind, Span, ToleranceExpr, CmpOp};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
 
-#[derive(Error, Debug)]
-pub enum ParseError {
-    #[error("an equals sign is not found")]
-    EqualsSignNotFound,
-    #[error("a closing parenthesis is missing")]
-    MissingCloseParen,
-    #[error("operation is not defined")]
-    TypeError,
-    #[error("Undefined field name: {0}")]
-    UndefinedField(String),
+pub use crate::parser::{ParseError, ErrorKind};
+
+/// Returns the names of columns, which the parser reads verbatim
+fn column_names<'c>(columns: &[&'c Columns]) -> Vec<&'c str> {
+    columns.iter()
+        .flat_map(|cols| cols.iter().map(|col| col.name()))
+        .collect()
 }
 
+/// Parses `lhs = rhs` where each side is a term or a tuple of terms
 pub fn parse_equal(s: &str, rcols: &Columns, lcols: &Columns) -> Result<(Vec<Term>, Vec<Term>), ParseError> {
-    let pos = s.find('=')
-        .ok_or(ParseError::EqualsSignNotFound)?;
+    let (rhs, lhs) = Parser::with_names(s, &column_names(&[rcols, lcols]))?.equation()?;
 
-    let rhs = s[..pos].trim();
-    let lhs = s[pos+1..].trim();
+    let rterms = Lowering::new(s, rcols).terms(&rhs)?;
+    let lterms = Lowering::new(s, lcols).terms(&lhs)?;
+    if rterms.len() != lterms.len() {
+        let kind = ErrorKind::LengthMismatch {
+            left: rterms.len(),
+            right: lterms.len(),
+        };
+        return Err(ParseError::new(kind, Span { start: 0, end: s.len() }, s));
+    }
 
-    let rhs = parse_tuple_or_term(rhs, rcols)?;
-    let lhs = parse_tuple_or_term(lhs, lcols)?;
+    let (rterms, lterms) = rterms.into_iter()
+        .zip(lterms)
+        .enumerate()
+        .map(|(i, (rterm, lterm))| {
+            let (rty, lty) = (rterm.ty(rcols), lterm.ty(lcols));
+            if rty == lty {
+                return Ok((rterm, lterm));
+            }
+            if let Some(rterm) = coerce(&rterm, lty) {
+                return Ok((rterm, lterm));
+            }
+            if let Some(lterm) = coerce(&lterm, rty) {
+                return Ok((rterm, lterm));
+            }
+            let kind = ErrorKind::PositionTypeMismatch {
+                position: i + 1,
+                left: rty.to_string(),
+                right: lty.to_string(),
+            };
+            let span = Span {
+                start: element_span(&rhs, i).start,
+                end: element_span(&lhs, i).end,
+            };
+            Err(ParseError::new(kind, span, s))
+        })
+        .collect::<Result<Vec<(Term, Term)>, ParseError>>()?
+        .into_iter()
+        .unzip();
 
-    Ok((rhs, lhs))
+    Ok((rterms, lterms))
 }
 
-fn parse_tuple_or_term(s: &str, cols: &Columns) -> Result<Vec<Term>, ParseError> {
-    if let Some(tuple) = parse_tuple(s, cols)? {
-        Ok(tuple.0)
-    } else {
-        Ok(vec![parse_term(s, cols)?])
+/// Returns the span of the `index`-th element of a tuple, or of the expression itself
+fn element_span(expr: &Expr, index: usize) -> Span {
+    match expr.kind {
+        ExprKind::Tuple(ref exprs) => exprs[index].span,
+        _ => expr.span,
     }
 }
 
-#[derive(Debug,Clone,PartialEq)]
-pub struct Tuple(Vec<Term>);
+/// Type of the value of a term
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Type {
+    String,
+    Decimal,
+    Date,
+    Integer,
+    Bool,
+    Time,
+    DateTime,
+}
+
+impl From<&Tag> for Type {
+    fn from(tag: &Tag) -> Type {
+        match tag {
+            Tag::String => Type::String,
+            Tag::Decimal(_) => Type::Decimal,
+            Tag::Date(_) => Type::Date,
+            Tag::Integer => Type::Integer,
+            Tag::Bool => Type::Bool,
+            Tag::Time(_) => Type::Time,
+            Tag::DateTime(_) => Type::DateTime,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Type::String => "String",
+            Type::Decimal => "Decimal",
+            Type::Date => "Date",
+            Type::Integer => "Integer",
+            Type::Bool => "Bool",
+            Type::Time => "Time",
+            Type::DateTime => "DateTime",
+        };
+        write!(f, "{}", name)
+    }
+}
 
-fn parse_tuple(s: &str, cols: &Columns) -> Result<Option<Tuple>, ParseError> {
-    let s = s.trim();
+/// Compiled regular expression compared by its source
+#[derive(Debug,Clone)]
+pub struct Pattern(Regex);
 
-    if s.starts_with('(') {
-        let end = s.find(')')
-            .ok_or(ParseError::MissingCloseParen)?;
-        Ok(Some(Tuple(parse_terms(&s[1..end], cols)?)))
-    } else {
-        Ok(None)
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.0.as_str() == other.0.as_str()
     }
 }
 
-fn parse_terms(s: &str, cols: &Columns) -> Result<Vec<Term>, ParseError> {
-    let s = s.trim();
+impl Eq for Pattern {}
+
+impl std::hash::Hash for Pattern {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+/// Function applied to the value of a term
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub enum Func {
+    Lower,
+    Upper,
+    Trim,
+    /// Replaces runs of whitespace with a space and trims both ends
+    CollapseWs,
+    /// Unicode normalization form KC, which folds full-width letters and half-width katakana
+    Nfkc,
+    /// Characters from a one-based position, up to a number of them if given
+    Substr(usize, Option<usize>),
+    Replace(String, String),
+    /// The first capture group of the first match, or the whole match if there are no groups
+    RegexExtract(Pattern),
+    Year,
+    /// Month of a date from 1 to 12
+    Month,
+    /// Abbreviated name of the day of the week like `Mon`
+    Weekday,
+    /// Monday of the week of a date
+    TruncWeek,
+    /// First day of the month of a date
+    TruncMonth,
+    /// Date itself on weekdays, or the next Monday on weekends
+    BusinessDay,
+}
+
+impl Func {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Func::Lower => "lower",
+            Func::Upper => "upper",
+            Func::Trim => "trim",
+            Func::CollapseWs => "collapse_ws",
+            Func::Nfkc => "nfkc",
+            Func::Substr(_, _) => "substr",
+            Func::Replace(_, _) => "replace",
+            Func::RegexExtract(_) => "regex_extract",
+            Func::Year => "year",
+            Func::Month => "month",
+            Func::Weekday => "weekday",
+            Func::TruncWeek => "trunc_week",
+            Func::TruncMonth => "trunc_month",
+            Func::BusinessDay => "business_day",
+        }
+    }
+
+    /// Returns the type of the argument
+    pub fn arg_ty(&self) -> Type {
+        match self {
+            Func::Year | Func::Month | Func::Weekday | Func::TruncWeek | Func::TruncMonth | Func::BusinessDay => Type::Date,
+            _ => Type::String,
+        }
+    }
+
+    /// Returns the type of the result
+    pub fn ty(&self) -> Type {
+        match self {
+            Func::Year | Func::Month => Type::Integer,
+            Func::TruncWeek | Func::TruncMonth | Func::BusinessDay => Type::Date,
+            _ => Type::String,
+        }
+    }
+
+    /// Applies the function to a value, yielding null for null or a missing match
+    fn apply(&self, value: Value) -> Value {
+        match value {
+            Value::String(s) => self.apply_string(s),
+            Value::Date(date) => self.apply_date(date),
+            _ => Value::Null,
+        }
+    }
+
+    fn apply_date(&self, date: NaiveDate) -> Value {
+        match self {
+            Func::Year => Value::Integer(date.year().into()),
+            Func::Month => Value::Integer(date.month().into()),
+            Func::Weekday => Value::String(date.weekday().to_string()),
+            Func::TruncWeek => Value::Date(date - Duration::days(date.weekday().num_days_from_monday().into())),
+            Func::TruncMonth => Value::Date(date.with_day(1).unwrap()),
+            Func::BusinessDay => {
+                let days = match date.weekday() {
+                    Weekday::Sat => 2,
+                    Weekday::Sun => 1,
+                    _ => 0,
+                };
+                date.checked_add_signed(Duration::days(days))
+                    .map(Value::Date)
+                    .unwrap_or(Value::Null)
+            },
+            _ => Value::Null,
+        }
+    }
+
+    fn apply_string(&self, s: String) -> Value {
+        let result = match self {
+            Func::Lower => s.to_lowercase(),
+            Func::Upper => s.to_uppercase(),
+            Func::Trim => s.trim().to_owned(),
+            Func::CollapseWs => s.split_whitespace().collect::<Vec<_>>().join(" "),
+            Func::Nfkc => s.nfkc().collect(),
+            Func::Substr(start, len) => {
+                let chars = s.chars().skip(start - 1);
+                match len {
+                    Some(len) => chars.take(*len).collect(),
+                    None => chars.collect(),
+                }
+            },
+            Func::Replace(from, to) => s.replace(from.as_str(), to),
+            Func::RegexExtract(Pattern(regex)) => {
+                let extracted = regex.captures(&s)
+                    .and_then(|caps| caps.get(1).or_else(|| caps.get(0)))
+                    .map(|m| m.as_str().to_owned());
+                match extracted {
+                    Some(extracted) => extracted,
+                    None => return Value::Null,
+                }
+            },
+            _ => return Value::Null,
+        };
+        Value::String(result)
+    }
 
-    if s.len() == 0 {
-        Ok(Vec::new())
-    } else {
-        s.split(',')
-            .map(|s| s.trim())
-            .map(|s| parse_term(s, cols))
-            .collect()
+    /// Writes the arguments following the first one like `, 1, 3`
+    fn describe_args(&self) -> String {
+        match self {
+            Func::Substr(start, Some(len)) => format!(", {}, {}", start, len),
+            Func::Substr(start, None) => format!(", {}", start),
+            Func::Replace(from, to) => format!(", {:?}, {:?}", from, to),
+            Func::RegexExtract(Pattern(regex)) => format!(", {:?}", regex.as_str()),
+            _ => String::new(),
+        }
     }
 }
 
+/// Expression over the fields of a record
+///
+/// Terms are type-checked against columns when they are parsed,
+/// so evaluation yields null instead of failing on overflows or division by zero.
 #[derive(Debug,Clone,PartialEq,Eq,Hash)]
 pub enum Term {
     Val(usize),
-    Neg(usize),
+    /// Sum of decimal terms
+    Sum(Vec<Term>),
+    /// Literal value like `"JPY"` or `100`
+    Lit(Value),
+    /// Product of decimal terms
+    Mul(Box<Term>, Box<Term>),
+    /// Quotient of decimal terms
+    Div(Box<Term>, Box<Term>),
+    /// Absolute value of a decimal or an integer term
+    Abs(Box<Term>),
+    /// Negative of a decimal or an integer term
+    Negate(Box<Term>),
+    /// Function applied to a term like `lower(description)`
+    Func(Func, Box<Term>),
+    /// Date shifted by a number of days
+    AddDays(Box<Term>, i64),
 }
 
 impl Term {
     pub fn eval(&self, fields: &[Value]) -> Value {
         match *self {
             Term::Val(index) => fields.get(index).cloned().unwrap(),
-            Term::Neg(index) => {
-                match fields.get(index).cloned().unwrap() {
+            Term::Sum(ref terms) => {
+                let sum = terms.iter()
+                    .try_fold(Decimal::new(0, 0), |acc, term| match term.eval(fields) {
+                        Value::Decimal(x) => acc.checked_add(x),
+                        _ => None,
+                    });
+                sum.map(Value::Decimal).unwrap_or(Value::Null)
+            },
+            Term::Lit(ref value) => value.clone(),
+            Term::Mul(ref lhs, ref rhs) => {
+                match (lhs.eval(fields), rhs.eval(fields)) {
+                    (Value::Decimal(x), Value::Decimal(y)) => x.checked_mul(y).map(Value::Decimal).unwrap_or(Value::Null),
+                    _ => Value::Null,
+                }
+            },
+            Term::Div(ref lhs, ref rhs) => {
+                match (lhs.eval(fields), rhs.eval(fields)) {
+                    (Value::Decimal(x), Value::Decimal(y)) => x.checked_div(y).map(Value::Decimal).unwrap_or(Value::Null),
+                    _ => Value::Null,
+                }
+            },
+            Term::Abs(ref term) => {
+                match term.eval(fields) {
+                    Value::Decimal(x) => Value::Decimal(x.abs()),
+                    Value::Integer(x) => x.checked_abs().map(Value::Integer).unwrap_or(Value::Null),
+                    _ => Value::Null,
+                }
+            },
+            Term::Negate(ref term) => {
+                match term.eval(fields) {
                     Value::Decimal(x) => Value::Decimal(-x),
-                    Value::String(_) => panic!("Negative of String is undefined"),
-                    Value::Date(_) => panic!("Negative of Date is undefined"),
+                    Value::Integer(x) => x.checked_neg().map(Value::Integer).unwrap_or(Value::Null),
+                    _ => Value::Null,
+                }
+            },
+            Term::Func(ref func, ref term) => func.apply(term.eval(fields)),
+            Term::AddDays(ref term, days) => {
+                match term.eval(fields) {
+                    Value::Date(date) => date.checked_add_signed(Duration::days(days))
+                        .map(Value::Date)
+                        .unwrap_or(Value::Null),
+                    _ => Value::Null,
+                }
+            },
+        }
+    }
+
+    /// Returns the type of the value of the term
+    pub fn ty(&self, columns: &Columns) -> Type {
+        match *self {
+            Term::Val(index) => columns.get_by_index(index)
+                .map(|(_, tag)| Type::from(tag))
+                .expect("a field out of columns"),
+            Term::Sum(_) | Term::Mul(_, _) | Term::Div(_, _) => Type::Decimal,
+            Term::Lit(Value::String(_)) => Type::String,
+            Term::Lit(Value::Decimal(_)) => Type::Decimal,
+            Term::Lit(Value::Date(_)) => Type::Date,
+            Term::Lit(Value::Integer(_)) => Type::Integer,
+            Term::Lit(Value::Bool(_)) => Type::Bool,
+            Term::Lit(Value::Time(_)) => Type::Time,
+            Term::Lit(Value::DateTime(_)) => Type::DateTime,
+            Term::Lit(Value::Null) => unreachable!("a null literal"),
+            Term::Abs(ref term) | Term::Negate(ref term) => term.ty(columns),
+            Term::Func(ref func, _) => func.ty(),
+            Term::AddDays(_, _) => Type::Date,
+        }
+    }
+
+    /// Returns the negative of a decimal or an integer term
+    fn negate(self) -> Term {
+        match self {
+            Term::Lit(Value::Decimal(x)) => Term::Lit(Value::Decimal(-x)),
+            Term::Sum(terms) => Term::Sum(terms.into_iter().map(Term::negate).collect()),
+            Term::Mul(lhs, rhs) => Term::Mul(Box::new(lhs.negate()), rhs),
+            Term::Div(lhs, rhs) => Term::Div(Box::new(lhs.negate()), rhs),
+            Term::Negate(term) => *term,
+            term => Term::Negate(Box::new(term)),
+        }
+    }
+
+    /// Returns the term without its leading minus sign if it has one
+    fn strip_sign(&self) -> Option<Term> {
+        match *self {
+            Term::Lit(Value::Decimal(x)) if x.is_sign_negative() => Some(Term::Lit(Value::Decimal(-x))),
+            Term::Mul(ref lhs, ref rhs) => lhs.strip_sign().map(|lhs| Term::Mul(Box::new(lhs), rhs.clone())),
+            Term::Div(ref lhs, ref rhs) => lhs.strip_sign().map(|lhs| Term::Div(Box::new(lhs), rhs.clone())),
+            Term::Negate(ref term) => Some((**term).clone()),
+            _ => None,
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Term::Sum(_) | Term::AddDays(_, _) => 1,
+            Term::Mul(_, _) | Term::Div(_, _) => 2,
+            _ => 3,
+        }
+    }
+
+    /// Writes the term with column names
+    pub fn describe(&self, columns: &Columns) -> String {
+        self.describe_in(columns, 0)
+    }
+
+    /// Writes the term in parentheses if it binds more loosely than `precedence`
+    fn describe_in(&self, columns: &Columns, precedence: u8) -> String {
+        let name = |index: usize| columns.get_by_index(index)
+            .map(|(name, _)| name.to_owned())
+            .unwrap_or_default();
+        let s = match *self {
+            Term::Val(index) => name(index),
+            Term::Sum(ref terms) => {
+                let mut s = String::new();
+                for (i, term) in terms.iter().enumerate() {
+                    match term.strip_sign() {
+                        _ if i == 0 => s.push_str(&term.describe_in(columns, 1)),
+                        Some(term) => s.push_str(&format!(" - {}", term.describe_in(columns, 2))),
+                        None => s.push_str(&format!(" + {}", term.describe_in(columns, 2))),
+                    }
+                }
+                s
+            },
+            Term::Lit(Value::String(ref s)) => format!("{:?}", s),
+            Term::Lit(ref value) => value.to_string(),
+            Term::Mul(ref lhs, ref rhs) => format!("{} * {}", lhs.describe_in(columns, 2), rhs.describe_in(columns, 3)),
+            Term::Div(ref lhs, ref rhs) => format!("{} / {}", lhs.describe_in(columns, 2), rhs.describe_in(columns, 3)),
+            Term::Abs(ref term) => format!("abs({})", term.describe(columns)),
+            Term::Negate(ref term) => format!("-{}", term.describe_in(columns, 3)),
+            Term::Func(ref func, ref term) => format!("{}({}{})", func.name(), term.describe(columns), func.describe_args()),
+            Term::AddDays(ref term, days) => {
+                let sign = if days < 0 { "-" } else { "+" };
+                let unit = if days.abs() == 1 { "day" } else { "days" };
+                format!("{} {} {} {}", term.describe_in(columns, 1), sign, days.abs(), unit)
+            },
+        };
+        if self.precedence() < precedence {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+}
+
+/// Converts syntax trees into terms over the columns of a table
+struct Lowering<'a> {
+    input: &'a str,
+    columns: &'a Columns,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(input: &'a str, columns: &'a Columns) -> Self {
+        Lowering { input, columns }
+    }
+
+    fn error(&self, kind: ErrorKind, span: Span) -> ParseError {
+        ParseError::new(kind, span, self.input)
+    }
+
+    fn type_error(&self, expr: &Expr, ty: Type, expected: &str) -> ParseError {
+        let kind = ErrorKind::TypeError {
+            expr: self.input[expr.span.start..expr.span.end].to_owned(),
+            ty: ty.to_string(),
+            expected: expected.to_owned(),
+        };
+        self.error(kind, expr.span)
+    }
+
+    /// Lowers a side of an equation, which is a term or a tuple of terms
+    fn terms(&self, expr: &Expr) -> Result<Vec<Term>, ParseError> {
+        match expr.kind {
+            ExprKind::Tuple(ref exprs) => exprs.iter()
+                .map(|expr| match expr.kind {
+                    ExprKind::Tuple(_) => Err(self.error(ErrorKind::NestedTuple("nested".to_owned()), expr.span)),
+                    _ => self.term(expr),
+                })
+                .collect(),
+            _ => Ok(vec![self.term(expr)?]),
+        }
+    }
+
+    /// Lowers an expression which must evaluate to a decimal
+    fn decimal(&self, expr: &Expr, expected: &str) -> Result<Term, ParseError> {
+        let term = self.term(expr)?;
+        match term.ty(self.columns) {
+            Type::Decimal => Ok(term),
+            ty => Err(self.type_error(expr, ty, expected)),
+        }
+    }
+
+    /// Checks the type of an operand of `+`, `-`, `*` or `/`
+    fn arithmetic(&self, term: Term, expr: &Expr, expected: &str) -> Result<Term, ParseError> {
+        match term.ty(self.columns) {
+            Type::Decimal => Ok(term),
+            Type::Integer => {
+                let kind = ErrorKind::IntegerArithmetic(self.input[expr.span.start..expr.span.end].to_owned());
+                Err(self.error(kind, expr.span))
+            },
+            ty => Err(self.type_error(expr, ty, expected)),
+        }
+    }
+
+    fn term(&self, expr: &Expr) -> Result<Term, ParseError> {
+        let mut addends = Vec::new();
+        collect_addends(expr, false, &mut addends);
+        if addends.iter().any(|(_, operand)| matches!(operand.kind, ExprKind::Days(_))) {
+            return self.shifted_date(expr, &addends);
+        }
+        if addends.len() == 1 {
+            let (negative, operand) = addends[0];
+            return self.signed(operand, negative);
+        }
+
+        addends.into_iter()
+            .map(|(negative, operand)| {
+                let term = self.signed(operand, negative)?;
+                self.arithmetic(term, operand, "a decimal for addition")
+            })
+            .collect::<Result<Vec<Term>, ParseError>>()
+            .map(Term::Sum)
+    }
+
+    /// Lowers a date plus or minus numbers of days like `date + 2 days`
+    fn shifted_date(&self, expr: &Expr, addends: &[(bool, &Expr)]) -> Result<Term, ParseError> {
+        let mut date = None;
+        let mut days = 0i64;
+        for &(negative, operand) in addends {
+            match operand.kind {
+                ExprKind::Days(ref n) => {
+                    let n = n.parse::<i64>()
+                        .map_err(|_| self.error(ErrorKind::InvalidNumber(n.to_owned()), operand.span))?;
+                    days += if negative { -n } else { n };
+                },
+                _ if date.is_some() || negative => return Err(self.literal_error(operand, "a number of days")),
+                _ => {
+                    let term = self.operand(operand)?;
+                    match term.ty(self.columns) {
+                        Type::Date => date = Some(term),
+                        ty => return Err(self.type_error(operand, ty, "a date for adding days")),
+                    }
+                },
+            }
+        }
+        let date = date.ok_or_else(|| self.literal_error(expr, "a date plus or minus days"))?;
+        Ok(Term::AddDays(Box::new(date), days))
+    }
+
+    /// Lowers an operand of additions with its sign
+    fn signed(&self, expr: &Expr, negative: bool) -> Result<Term, ParseError> {
+        let term = self.operand(expr)?;
+        match term.ty(self.columns) {
+            _ if !negative => Ok(term),
+            Type::Decimal | Type::Integer => Ok(term.negate()),
+            ty => Err(self.type_error(expr, ty, "a decimal or an integer for negation")),
+        }
+    }
+
+    fn operand(&self, expr: &Expr) -> Result<Term, ParseError> {
+        match expr.kind {
+            ExprKind::Field(ref name) => {
+                let (index, _) = self.columns.get_by_name(name)
+                    .ok_or_else(|| self.error(ErrorKind::UndefinedField(name.to_owned()), expr.span))?;
+                Ok(Term::Val(index))
+            },
+            ExprKind::Number(ref n) => {
+                let x = n.parse::<Decimal>()
+                    .map_err(|_| self.error(ErrorKind::InvalidNumber(n.to_owned()), expr.span))?;
+                Ok(Term::Lit(Value::Decimal(x)))
+            },
+            ExprKind::Str(ref s) => Ok(Term::Lit(Value::String(s.to_owned()))),
+            ExprKind::Mul(ref lhs, ref rhs) => {
+                let lhs = self.arithmetic(self.term(lhs)?, lhs, "a decimal for multiplication")?;
+                let rhs = self.arithmetic(self.term(rhs)?, rhs, "a decimal for multiplication")?;
+                Ok(Term::Mul(Box::new(lhs), Box::new(rhs)))
+            },
+            ExprKind::Div(ref lhs, ref rhs) => {
+                let lhs = self.arithmetic(self.term(lhs)?, lhs, "a decimal for division")?;
+                let rhs = self.arithmetic(self.term(rhs)?, rhs, "a decimal for division")?;
+                Ok(Term::Div(Box::new(lhs), Box::new(rhs)))
+            },
+            ExprKind::Call(ref name, ref args) => self.call(expr, name, args),
+            ExprKind::Tuple(_) => Err(self.error(ErrorKind::NestedTuple("an operand".to_owned()), expr.span)),
+            ExprKind::Days(_) => unreachable!("days are added to dates"),
+            ExprKind::Compare(_, _, _) | ExprKind::Between(_, _, _) | ExprKind::In(_, _) | ExprKind::Matches(_, _)
+                | ExprKind::Not(_) | ExprKind::And(_, _) | ExprKind::Or(_, _) => Err(self.literal_error(expr, "a term")),
+            ExprKind::Neg(_) | ExprKind::Add(_, _) | ExprKind::Sub(_, _) => unreachable!("additions are flattened"),
+        }
+    }
+
+    fn call(&self, expr: &Expr, name: &str, args: &[Expr]) -> Result<Term, ParseError> {
+        let (min, max) = match name {
+            "abs" | "lower" | "upper" | "trim" | "collapse_ws" | "nfkc" => (1, 1),
+            "year" | "month" | "weekday" | "trunc_week" | "trunc_month" | "business_day" => (1, 1),
+            "substr" => (2, 3),
+            "replace" => (3, 3),
+            "regex_extract" => (2, 2),
+            _ => return Err(self.error(ErrorKind::UnknownFunction(name.to_owned()), expr.span)),
+        };
+        if args.len() < min || args.len() > max {
+            let kind = ErrorKind::WrongArguments {
+                name: name.to_owned(),
+                expected: if min == max { min.to_string() } else { format!("{} or {}", min, max) },
+                found: args.len(),
+            };
+            return Err(self.error(kind, expr.span));
+        }
+
+        let arg = self.term(&args[0])?;
+        let func = match name {
+            "abs" => return match arg.ty(self.columns) {
+                Type::Decimal | Type::Integer => Ok(Term::Abs(Box::new(arg))),
+                ty => Err(self.type_error(&args[0], ty, "a decimal or an integer for `abs`")),
+            },
+            "lower" => Func::Lower,
+            "upper" => Func::Upper,
+            "trim" => Func::Trim,
+            "collapse_ws" => Func::CollapseWs,
+            "nfkc" => Func::Nfkc,
+            "substr" => {
+                let len = args.get(2).map(|arg| self.positive_literal(arg)).transpose()?;
+                Func::Substr(self.positive_literal(&args[1])?, len)
+            },
+            "replace" => Func::Replace(self.string_literal(&args[1])?, self.string_literal(&args[2])?),
+            "regex_extract" => {
+                let regex = Regex::new(&self.string_literal(&args[1])?)
+                    .map_err(|e| self.error(ErrorKind::InvalidRegex(e.to_string()), args[1].span))?;
+                Func::RegexExtract(Pattern(regex))
+            },
+            "year" => Func::Year,
+            "month" => Func::Month,
+            "weekday" => Func::Weekday,
+            "trunc_week" => Func::TruncWeek,
+            "trunc_month" => Func::TruncMonth,
+            "business_day" => Func::BusinessDay,
+            _ => unreachable!("arities are defined above"),
+        };
+        match (arg.ty(self.columns), func.arg_ty()) {
+            (ty, expected) if ty == expected => Ok(Term::Func(func, Box::new(arg))),
+            (ty, Type::Date) => Err(self.type_error(&args[0], ty, &format!("a date for `{}`", name))),
+            (ty, _) => Err(self.type_error(&args[0], ty, &format!("a string for `{}`", name))),
+        }
+    }
+
+    fn condition(&self, expr: &Expr) -> Result<Filter, ParseError> {
+        match expr.kind {
+            ExprKind::Not(ref operand) => Ok(Filter::Not(Box::new(self.condition(operand)?))),
+            ExprKind::And(ref lhs, ref rhs) => Ok(Filter::And(Box::new(self.condition(lhs)?), Box::new(self.condition(rhs)?))),
+            ExprKind::Or(ref lhs, ref rhs) => Ok(Filter::Or(Box::new(self.condition(lhs)?), Box::new(self.condition(rhs)?))),
+            ExprKind::Compare(op, ref lhs, ref rhs) => {
+                let lhs_term = self.term(lhs)?;
+                let rhs_term = self.term(rhs)?;
+                // A literal on the left side is converted to the type of the right side
+                if let Some(lhs_term) = coerce(&lhs_term, rhs_term.ty(self.columns)) {
+                    return Ok(Filter::Compare(op, lhs_term, rhs_term));
+                }
+                let rhs_term = self.compared(&lhs_term, lhs, rhs_term, rhs)?;
+                Ok(Filter::Compare(op, lhs_term, rhs_term))
+            },
+            ExprKind::Between(ref x, ref low, ref high) => {
+                let term = self.term(x)?;
+                let low = self.compared(&term, x, self.term(low)?, low)?;
+                let high = self.compared(&term, x, self.term(high)?, high)?;
+                Ok(Filter::Between(term, low, high))
+            },
+            ExprKind::In(ref x, ref items) => {
+                let term = self.term(x)?;
+                let items = items.iter()
+                    .map(|item| self.compared(&term, x, self.term(item)?, item))
+                    .collect::<Result<Vec<Term>, ParseError>>()?;
+                Ok(Filter::In(term, items))
+            },
+            ExprKind::Matches(ref x, ref pattern) => {
+                let term = self.term(x)?;
+                let regex = Regex::new(&self.string_literal(pattern)?)
+                    .map_err(|e| self.error(ErrorKind::InvalidRegex(e.to_string()), pattern.span))?;
+                match term.ty(self.columns) {
+                    Type::String => Ok(Filter::Matches(term, Pattern(regex))),
+                    ty => Err(self.type_error(x, ty, "a string for `matches`")),
+                }
+            },
+            _ => {
+                let term = self.term(expr)?;
+                match term.ty(self.columns) {
+                    Type::Bool => Ok(Filter::Is(term)),
+                    ty => Err(self.type_error(expr, ty, "a condition")),
+                }
+            },
+        }
+    }
+
+    /// Checks that a term is comparable with another one, converting a literal to its type
+    fn compared(&self, lhs: &Term, lhs_expr: &Expr, rhs: Term, rhs_expr: &Expr) -> Result<Term, ParseError> {
+        let (lhs_ty, rhs_ty) = (lhs.ty(self.columns), rhs.ty(self.columns));
+        if lhs_ty == rhs_ty {
+            return Ok(rhs);
+        }
+        coerce(&rhs, lhs_ty).ok_or_else(|| {
+            let lhs_text = &self.input[lhs_expr.span.start..lhs_expr.span.end];
+            self.type_error(rhs_expr, rhs_ty, &format!("{} to compare with `{}`", lhs_ty, lhs_text))
+        })
+    }
+
+    fn literal_error(&self, expr: &Expr, expected: &str) -> ParseError {
+        let kind = ErrorKind::Unexpected {
+            expected: expected.to_owned(),
+            found: format!("`{}`", &self.input[expr.span.start..expr.span.end]),
+        };
+        self.error(kind, expr.span)
+    }
+
+    fn string_literal(&self, expr: &Expr) -> Result<String, ParseError> {
+        match expr.kind {
+            ExprKind::Str(ref s) => Ok(s.clone()),
+            _ => Err(self.literal_error(expr, "a string literal")),
+        }
+    }
+
+    fn positive_literal(&self, expr: &Expr) -> Result<usize, ParseError> {
+        match expr.kind {
+            ExprKind::Number(ref n) => n.parse::<usize>().ok().filter(|&n| n > 0)
+                .ok_or_else(|| self.literal_error(expr, "a positive integer")),
+            _ => Err(self.literal_error(expr, "a positive integer")),
+        }
+    }
+}
+
+/// Converts a literal to a type like `"2020-09-01"` to a date, or returns `None` if it cannot be
+fn coerce(term: &Term, ty: Type) -> Option<Term> {
+    match (term, ty) {
+        (Term::Lit(Value::Decimal(x)), Type::Integer) if x.fract() == Decimal::new(0, 0) => {
+            x.to_i64().map(|x| Term::Lit(Value::Integer(x)))
+        },
+        (Term::Lit(Value::String(s)), Type::Date) => {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(|date| Term::Lit(Value::Date(date)))
+        },
+        _ => None,
+    }
+}
+
+/// Flattens additions, subtractions and negations into signed operands
+fn collect_addends<'e>(expr: &'e Expr, negative: bool, addends: &mut Vec<(bool, &'e Expr)>) {
+    match expr.kind {
+        ExprKind::Neg(ref operand) => collect_addends(operand, !negative, addends),
+        ExprKind::Add(ref lhs, ref rhs) => {
+            collect_addends(lhs, negative, addends);
+            collect_addends(rhs, negative, addends);
+        },
+        ExprKind::Sub(ref lhs, ref rhs) => {
+            collect_addends(lhs, negative, addends);
+            collect_addends(rhs, !negative, addends);
+        },
+        _ => addends.push((negative, expr)),
+    }
+}
+
+/// Condition on the fields of a record like `amount != 0 and not description matches "^Opening"`
+#[derive(Debug,Clone,PartialEq)]
+pub enum Filter {
+    Compare(CmpOp, Term, Term),
+    /// Inclusive range
+    Between(Term, Term, Term),
+    In(Term, Vec<Term>),
+    Matches(Term, Pattern),
+    /// Value of a boolean term
+    Is(Term),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    /// Returns `true` if a record satisfies the condition
+    ///
+    /// Like `WHERE` of SQL, comparisons with null are unknown and a record is kept only if the condition is true.
+    pub fn test(&self, fields: &[Value]) -> bool {
+        self.eval(fields) == Some(true)
+    }
+
+    fn eval(&self, fields: &[Value]) -> Option<bool> {
+        let non_null = |term: &Term| Some(term.eval(fields)).filter(|value| *value != Value::Null);
+        match self {
+            Filter::Compare(op, lhs, rhs) => {
+                let (lhs, rhs) = (non_null(lhs)?, non_null(rhs)?);
+                Some(match op {
+                    CmpOp::Eq => lhs == rhs,
+                    CmpOp::Ne => lhs != rhs,
+                    CmpOp::Lt => lhs < rhs,
+                    CmpOp::Le => lhs <= rhs,
+                    CmpOp::Gt => lhs > rhs,
+                    CmpOp::Ge => lhs >= rhs,
+                })
+            },
+            Filter::Between(x, low, high) => {
+                let (x, low, high) = (non_null(x)?, non_null(low)?, non_null(high)?);
+                Some(low <= x && x <= high)
+            },
+            Filter::In(x, items) => {
+                let x = non_null(x)?;
+                let items: Vec<Option<Value>> = items.iter().map(non_null).collect();
+                if items.iter().any(|item| item.as_ref() == Some(&x)) {
+                    Some(true)
+                } else if items.iter().any(Option::is_none) {
+                    None
+                } else {
+                    Some(false)
                 }
             },
+            Filter::Matches(x, Pattern(regex)) => match x.eval(fields) {
+                Value::String(s) => Some(regex.is_match(&s)),
+                _ => None,
+            },
+            Filter::Is(term) => match term.eval(fields) {
+                Value::Bool(b) => Some(b),
+                _ => None,
+            },
+            Filter::Not(filter) => filter.eval(fields).map(|b| !b),
+            Filter::And(lhs, rhs) => match (lhs.eval(fields), rhs.eval(fields)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Filter::Or(lhs, rhs) => match (lhs.eval(fields), rhs.eval(fields)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Parses a condition on records of a table like `amount != 0 and date between "2020-09-01" and "2020-09-30"`
+pub fn parse_filter(s: &str, columns: &Columns) -> Result<Filter, ParseError> {
+    let condition = Parser::with_names(s, &column_names(&[columns]))?.condition_to_end()?;
+    Lowering::new(s, columns).condition(&condition)
+}
+
+/// Equality of sums of a source term and a target term over classes like `-price = price`
+#[derive(Debug,Clone,PartialEq)]
+pub struct SumEqual {
+    name: String,
+    source: Term,
+    target: Term,
+}
+
+impl SumEqual {
+    /// Returns the source term with column names
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of the source term, or `None` if it is null
+    pub fn source_value(&self, record: &[Value]) -> Option<Decimal> {
+        decimal_of(&self.source, record)
+    }
+
+    /// Returns the value of the target term, or `None` if it is null
+    pub fn target_value(&self, record: &[Value]) -> Option<Decimal> {
+        decimal_of(&self.target, record)
+    }
+
+    /// Returns the sum of the source term over records, ignoring nulls
+    pub fn source_sum(&self, records: &[Record]) -> Decimal {
+        records.iter()
+            .filter_map(|record| self.source_value(record))
+            .fold(Decimal::new(0, 0), |acc, x| acc + x)
+    }
+
+    /// Returns the sum of the target term over records, ignoring nulls
+    pub fn target_sum(&self, records: &[Record]) -> Decimal {
+        records.iter()
+            .filter_map(|record| self.target_value(record))
+            .fold(Decimal::new(0, 0), |acc, x| acc + x)
+    }
+}
+
+fn decimal_of(term: &Term, record: &[Value]) -> Option<Decimal> {
+    match term.eval(record) {
+        Value::Decimal(x) => Some(x),
+        _ => None,
+    }
+}
+
+/// Parses `source_term = target_term` of decimal terms
+pub fn parse_sum_equal(s: &str, scols: &Columns, tcols: &Columns) -> Result<SumEqual, ParseError> {
+    let (source, target) = Parser::with_names(s, &column_names(&[scols, tcols]))?.equation()?;
+    let source = Lowering::new(s, scols).decimal(&source, "a decimal")?;
+    let target = Lowering::new(s, tcols).decimal(&target, "a decimal")?;
+
+    Ok(SumEqual {
+        name: source.describe(scols),
+        source,
+        target,
+    })
+}
+
+/// Maximum difference between approximately equal values
+#[derive(Debug,Clone,PartialEq)]
+pub enum Tolerance {
+    Decimal(Decimal),
+    /// Number of days between dates
+    Days(i64),
+}
+
+impl Tolerance {
+    /// Converts a decimal like `0.01` or a number of days like `3 days`
+    fn lower(tolerance: &ToleranceExpr, input: &str) -> Result<Tolerance, ParseError> {
+        let invalid = || ParseError::new(ErrorKind::InvalidTolerance(tolerance.value.clone()), tolerance.span, input);
+        if tolerance.days {
+            tolerance.value.parse::<i64>()
+                .map(|days| Tolerance::Days(days.abs()))
+                .map_err(|_| invalid())
+        } else {
+            tolerance.value.parse::<Decimal>()
+                .map(|d| Tolerance::Decimal(d.abs()))
+                .map_err(|_| invalid())
         }
     }
 }
 
-fn parse_term(s: &str, columns: &Columns) -> Result<Term, ParseError> {
-    let s = s.trim();
+/// Approximate equality of a source term and a target term like `price ~ price within 0.01`
+#[derive(Debug,Clone,PartialEq)]
+pub struct Approx {
+    name: String,
+    source: Term,
+    target: Term,
+    tolerance: Tolerance,
+}
 
-    if s.starts_with('-') {
-        let name = s[1..].trim();
-        let (index, tag) = columns.get_by_name(name)
-            .ok_or(ParseError::UndefinedField(name.to_owned()))?;
+impl Approx {
+    /// Returns the source term with column names
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-        if tag != Tag::Decimal {
-            return Err(ParseError::TypeError);
+    /// Returns the difference of the target value from the source value if it is within the tolerance
+    ///
+    /// The difference of dates is a number of days.
+    pub fn delta(&self, source: &[Value], target: &[Value]) -> Option<Decimal> {
+        match (&self.tolerance, self.source.eval(source), self.target.eval(target)) {
+            (Tolerance::Decimal(tol), Value::Decimal(x), Value::Decimal(y)) => {
+                Some(y - x).filter(|d| d.abs() <= *tol)
+            },
+            (Tolerance::Days(tol), Value::Date(x), Value::Date(y)) => {
+                let days = (y - x).num_days();
+                Some(Decimal::from(days)).filter(|_| days.abs() <= *tol)
+            },
+            _ => None,
         }
+    }
 
-        Ok(Term::Neg(index))
-    } else {
-        let (index, _) = columns.get_by_name(s)
-            .ok_or(ParseError::UndefinedField(s.to_owned()))?;
-        Ok(Term::Val(index))
+    /// Writes a difference with its sign like `+0.01` or `-2 days`
+    pub fn describe_delta(&self, delta: Decimal) -> String {
+        let sign = if delta > Decimal::new(0, 0) { "+" } else { "" };
+        match self.tolerance {
+            Tolerance::Decimal(_) => format!("{}{}", sign, delta),
+            Tolerance::Days(_) if delta.abs() == Decimal::from(1) => format!("{}{} day", sign, delta),
+            Tolerance::Days(_) => format!("{}{} days", sign, delta),
+        }
     }
 }
 
+/// Parses `source_term ~ target_term within tolerance`
+pub fn parse_approx(s: &str, scols: &Columns, tcols: &Columns) -> Result<Approx, ParseError> {
+    let (source_expr, target_expr, tolerance) = Parser::with_names(s, &column_names(&[scols, tcols]))?.approximation()?;
+    let tolerance = Tolerance::lower(&tolerance, s)?;
+
+    let lower = |expr: &Expr, cols: &Columns| {
+        let lowering = Lowering::new(s, cols);
+        match tolerance {
+            Tolerance::Days(_) => {
+                let term = lowering.term(expr)?;
+                match term.ty(cols) {
+                    Type::Date => Ok(term),
+                    ty => Err(lowering.type_error(expr, ty, "a date for a tolerance in days")),
+                }
+            },
+            Tolerance::Decimal(_) => lowering.decimal(expr, "a decimal"),
+        }
+    };
+    let source = lower(&source_expr, scols)?;
+    let target = lower(&target_expr, tcols)?;
+
+    Ok(Approx {
+        name: source.describe(scols),
+        source,
+        target,
+        tolerance,
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn parse_term(s: &str, cols: &Columns) -> Result<Term, ParseError> {
+        let expr = Parser::with_names(s, &column_names(&[cols]))?.expr()?;
+        Lowering::new(s, cols).term(&expr)
+    }
+
+    fn parse_terms(s: &str, cols: &Columns) -> Result<Vec<Term>, ParseError> {
+        let expr = Parser::with_names(s, &column_names(&[cols]))?.expr()?;
+        Lowering::new(s, cols).terms(&expr)
+    }
+
     #[test]
     fn term() {
         let cols = "key1:Decimal, key2:Decimal".parse::<Columns>().unwrap();
-        assert_eq!(parse_term("key1", &cols), Term::Val(0));
-        assert_eq!(parse_term("-key2", &cols), Term::Neg(1));
+        assert_eq!(parse_term("key1", &cols).unwrap(), Term::Val(0));
+        assert_eq!(parse_term("-key2", &cols).unwrap(), Term::Val(1).negate());
+    }
+
+    #[test]
+    fn sum() {
+        let cols = "deposit:Decimal, withdrawal:Decimal, memo".parse::<Columns>().unwrap();
+        assert_eq!(parse_term("deposit - withdrawal", &cols).unwrap(),
+            Term::Sum(vec![Term::Val(0), Term::Val(1).negate()]));
+        assert_eq!(parse_term("-deposit+withdrawal", &cols).unwrap(),
+            Term::Sum(vec![Term::Val(0).negate(), Term::Val(1)]));
+        assert!(parse_term("deposit - memo", &cols).is_err());
+        assert!(parse_term("deposit - ", &cols).is_err());
+
+        let record = vec![Value::Decimal(300.into()), Value::Decimal(1000.into()), Value::String(String::new())];
+        assert_eq!(Term::Sum(vec![Term::Val(0), Term::Val(1).negate()]).eval(&record), Value::Decimal((-700).into()));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let cols = "price:Decimal, fee:Decimal, qty:Integer, memo".parse::<Columns>().unwrap();
+        let record = vec![Value::Decimal("100.00".parse().unwrap()), Value::Decimal(3.into()), Value::Integer(-2), Value::Null];
+        let eval = |s: &str| parse_term(s, &cols).unwrap().eval(&record);
+        let decimal = |s: &str| Value::Decimal(s.parse().unwrap());
+
+        assert_eq!(eval("price * 1.1"), decimal("110"));
+        assert_eq!(eval("price - fee"), decimal("97"));
+        assert_eq!(eval("price / 100"), decimal("1"));
+        assert_eq!(eval("-(price - fee) * 2 + 4"), decimal("-190"));
+        assert_eq!(eval("abs(fee - price)"), decimal("97"));
+        assert_eq!(eval("abs(qty)"), Value::Integer(2));
+        assert_eq!(eval("-abs(fee)"), decimal("-3"));
+        assert_eq!(eval("-abs(qty)"), Value::Integer(-2));
+        assert_eq!(eval("-(-abs(qty))"), Value::Integer(2));
+        assert_eq!(parse_term("-abs(qty)", &cols).unwrap().ty(&cols), Type::Integer);
+        assert_eq!(parse_term("price - abs(fee)", &cols).unwrap().describe(&cols), "price - abs(fee)");
+        assert_eq!(eval("100"), decimal("100"));
+        assert_eq!(eval("price / (fee - 3)"), Value::Null);
+
+        assert_eq!(parse_term("(price - fee) * 1.1", &cols).unwrap().describe(&cols), "(price - fee) * 1.1");
+        assert_eq!(parse_term("price - fee * 2", &cols).unwrap().describe(&cols), "price - fee * 2");
+        assert_eq!(parse_term("-(price / fee)", &cols).unwrap().describe(&cols), "-price / fee");
+        assert_eq!(parse_term("price / (fee / 2)", &cols).unwrap().describe(&cols), "price / (fee / 2)");
+
+        let err = parse_term("price * memo", &cols).unwrap_err();
+        assert_eq!(err.to_string().lines().next().unwrap(),
+            "`memo` is String, but a decimal for multiplication is expected at column 9");
+        assert!(parse_term("qty / 2", &cols).is_err());
+        assert!(parse_term("abs(memo)", &cols).is_err());
+        assert_eq!(parse_term("round(price)", &cols).unwrap_err().kind(), &ErrorKind::UnknownFunction("round".to_owned()));
+        assert_eq!(parse_term("abs(price, fee)", &cols).unwrap_err().kind(), &ErrorKind::WrongArguments {
+            name: "abs".to_owned(),
+            expected: "1".to_owned(),
+            found: 2,
+        });
+    }
+
+    #[test]
+    fn strings() {
+        let cols = "memo, price:Decimal".parse::<Columns>().unwrap();
+        let eval = |s: &str, memo: &str| {
+            let record = vec![Value::String(memo.to_owned()), Value::Null];
+            parse_term(s, &cols).unwrap().eval(&record)
+        };
+        let string = |s: &str| Value::String(s.to_owned());
+
+        assert_eq!(eval("lower(nfkc(memo))", "ＡＭＡＺＯＮ"), string("amazon"));
+        assert_eq!(eval("nfkc(memo)", "ｽｰﾊﾟｰ"), string("スーパー"));
+        assert_eq!(eval("upper(trim(memo))", " cafe "), string("CAFE"));
+        assert_eq!(eval("collapse_ws(memo)", " Coffee \t Shop "), string("Coffee Shop"));
+        assert_eq!(eval("substr(memo, 2, 3)", "カード払い"), string("ード払"));
+        assert_eq!(eval("substr(memo, 3)", "カード払い"), string("ド払い"));
+        assert_eq!(eval(r#"replace(memo, "-", "")"#, "AB-12-3"), string("AB123"));
+        assert_eq!(eval(r##"regex_extract(memo, "#(\d+)")"##, "Order #1234 shipped"), string("1234"));
+        assert_eq!(eval(r#"regex_extract(memo, "[A-Z]+")"#, "paid by VISA"), string("VISA"));
+        assert_eq!(eval(r#"regex_extract(memo, "\d+")"#, "none"), Value::Null);
+        assert_eq!(parse_term("lower(memo)", &cols).unwrap().eval(&[Value::Null, Value::Null]), Value::Null);
+
+        assert_eq!(parse_term(r#"substr(lower(memo), 1, 4)"#, &cols).unwrap().describe(&cols), "substr(lower(memo), 1, 4)");
+        assert_eq!(parse_term(r#"replace(memo, "a,b", "")"#, &cols).unwrap().describe(&cols), r#"replace(memo, "a,b", "")"#);
+
+        assert_eq!(parse_term("lower(price)", &cols).unwrap_err().to_string().lines().next().unwrap(),
+            "`price` is Decimal, but a string for `lower` is expected at column 7");
+        assert!(parse_term("substr(memo, 0)", &cols).is_err());
+        assert!(parse_term("replace(memo, memo, \"\")", &cols).is_err());
+        assert!(matches!(parse_term(r#"regex_extract(memo, "(")"#, &cols).unwrap_err().kind(), ErrorKind::InvalidRegex(_)));
+        assert_eq!(parse_term("substr(memo)", &cols).unwrap_err().kind(), &ErrorKind::WrongArguments {
+            name: "substr".to_owned(),
+            expected: "2 or 3".to_owned(),
+            found: 1,
+        });
+    }
+
+    #[test]
+    fn dates() {
+        let cols = "date:Date, memo".parse::<Columns>().unwrap();
+        let day = |d| Value::Date(NaiveDate::from_ymd(2020, 9, d));
+        // 2020-09-12 is Saturday
+        let record = vec![day(12), Value::Null];
+        let eval = |s: &str| parse_term(s, &cols).unwrap().eval(&record);
+
+        assert_eq!(eval("year(date)"), Value::Integer(2020));
+        assert_eq!(eval("month(date)"), Value::Integer(9));
+        assert_eq!(eval("-year(date)"), Value::Integer(-2020));
+        assert_eq!(eval("weekday(date)"), Value::String("Sat".to_owned()));
+        assert_eq!(eval("trunc_week(date)"), day(7));
+        assert_eq!(eval("trunc_month(date)"), day(1));
+        assert_eq!(eval("business_day(date)"), day(14));
+        assert_eq!(eval("business_day(date + 1 day)"), day(14));
+        assert_eq!(eval("business_day(date - 1 day)"), day(11));
+        assert_eq!(eval("business_day(date - 2 days)"), day(10));
+        assert_eq!(eval("date + 2 days"), day(14));
+        assert_eq!(eval("-3 days + date + 1 day"), day(10));
+        assert_eq!(eval("trunc_week(date) + 7 days"), day(14));
+        assert_eq!(parse_term("month(date)", &cols).unwrap().eval(&[Value::Null, Value::Null]), Value::Null);
+
+        assert_eq!(parse_term("date - 1 day", &cols).unwrap().describe(&cols), "date - 1 day");
+        assert_eq!(parse_term("business_day(date + 2 days)", &cols).unwrap().describe(&cols), "business_day(date + 2 days)");
+
+        assert_eq!(parse_term("month(memo)", &cols).unwrap_err().to_string().lines().next().unwrap(),
+            "`memo` is String, but a date for `month` is expected at column 7");
+        assert!(parse_term("memo + 1 day", &cols).is_err());
+        assert!(parse_term("2 days", &cols).is_err());
+        assert!(parse_term("1 day - date", &cols).is_err());
+        assert!(parse_term("date + date + 1 day", &cols).is_err());
+        assert!(parse_term("date + 1", &cols).is_err());
+    }
+
+    #[test]
+    fn filters() {
+        let cols = "date:Date, memo, amount:Decimal, qty:Integer, void:Bool".parse::<Columns>().unwrap();
+        let record = vec![
+            Value::Date(NaiveDate::from_ymd(2020, 9, 12)),
+            Value::String("Opening balance".to_owned()),
+            Value::Decimal("0.00".parse().unwrap()),
+            Value::Integer(3),
+            Value::Null,
+        ];
+        let test = |s: &str| parse_filter(s, &cols).unwrap().test(&record);
+
+        assert!(test("amount = 0"));
+        assert!(test("0 = amount and qty > 2 and 2 < qty"));
+        assert!(test(r#"date between "2020-09-01" and "2020-09-30""#));
+        assert!(!test(r#"date < "2020-09-12" or date > "2020-09-12""#));
+        assert!(test(r#"memo matches "^Opening" and lower(memo) not in ("book", "cafe")"#));
+        assert!(test("not (amount != 0 or qty in (1, 2))"));
+        assert!(test("abs(amount - 1) * 3 >= 3"));
+
+        // comparisons with null are unknown
+        assert!(!test("void"));
+        assert!(!test("not void"));
+        assert!(test("void or amount = 0"));
+        assert!(!test("void and amount = 0"));
+        assert!(!test("not (void and amount = 0)"));
+        assert!(!test("void in (void)"));
+        assert!(!test("void not in (void)"));
+
+        assert_eq!(parse_filter("amount = memo", &cols).unwrap_err().to_string().lines().next().unwrap(),
+            "`memo` is String, but Decimal to compare with `amount` is expected at column 10");
+        assert!(parse_filter(r#"date > "yesterday""#, &cols).is_err());
+        assert!(parse_filter("qty = 1.5", &cols).is_err());
+        assert!(parse_filter("amount matches \"0\"", &cols).is_err());
+        assert!(parse_filter("amount", &cols).is_err());
+        assert!(parse_filter("amount + (qty > 1) = 1", &cols).is_err());
+    }
+
+    #[test]
+    fn verbatim_names() {
+        let rcols = "Posting Date:Date, unit-price:Decimal, 1st payment:Decimal, in:String".parse::<Columns>().unwrap();
+        let lcols = "day:Date, within:Decimal, matches:Decimal, between:String".parse::<Columns>().unwrap();
+        assert_eq!(parse_equal("(Posting Date, unit-price, 1st payment, in) = (day, within, matches, between)", &rcols, &lcols).unwrap(),
+            ((0..4).map(Term::Val).collect(), (0..4).map(Term::Val).collect()));
+        assert_eq!(parse_approx("Posting Date ~ day within 1 day", &rcols, &lcols).unwrap().tolerance, Tolerance::Days(1));
+        assert!(parse_approx("1st payment ~ within within 1", &rcols, &lcols).is_ok());
+        assert!(parse_filter(r#"in in ("a", in) and not 1st payment > 0"#, &rcols).is_ok());
+    }
+
+    #[test]
+    fn describe() {
+        let cols = "deposit:Decimal, withdrawal:Decimal".parse::<Columns>().unwrap();
+        assert_eq!(Term::Val(1).negate().describe(&cols), "-withdrawal");
+        assert_eq!(parse_term("-deposit + withdrawal", &cols).unwrap().describe(&cols), "-deposit + withdrawal");
+        assert_eq!(parse_term("deposit-withdrawal", &cols).unwrap().describe(&cols), "deposit - withdrawal");
     }
 
     #[test]
     fn tuple() {
         let cols = "x:Decimal, y:Decimal".parse::<Columns>().unwrap();
-        assert_eq!(parse_tuple("(x, y)", &cols), Some(Tuple(vec![Term::Val(0), Term::Val(1)])));
+        assert_eq!(parse_terms("(x, y)", &cols).unwrap(), vec![Term::Val(0), Term::Val(1)]);
     }
 
     #[test]
     fn tuple_neg() {
         let cols = "x:Decimal, y:Decimal".parse::<Columns>().unwrap();
-        assert_eq!(parse_tuple("(x, -y)", &cols), Some(Tuple(vec![Term::Val(0), Term::Neg(1)])));
+        assert_eq!(parse_terms("(x, -y)", &cols).unwrap(), vec![Term::Val(0), Term::Val(1).negate()]);
     }
 
     #[test]
     fn equal_term() {
         let rcols = "x: Decimal, y: Decimal".parse::<Columns>().unwrap();
         let lcols = "a: Decimal, b: Decimal".parse::<Columns>().unwrap();
-        assert_eq!(parse_equal("x = -b", &rcols, &lcols),
-            (vec![Term::Val(0)], vec![Term::Neg(1)]));
+        assert_eq!(parse_equal("x = -b", &rcols, &lcols).unwrap(),
+            (vec![Term::Val(0)], vec![Term::Val(1).negate()]));
     }
 
     #[test]
     fn equal_tuple() {
         let rcols = "x: Decimal, y: Decimal".parse::<Columns>().unwrap();
         let lcols = "a: Decimal, b: Decimal".parse::<Columns>().unwrap();
-        assert_eq!(parse_equal("(x, -y) = (b, a)", &rcols, &lcols),
-            (vec![Term::Val(0), Term::Neg(1)], vec![Term::Val(1), Term::Val(0)]));
+        assert_eq!(parse_equal("(x, -y) = (b, a)", &rcols, &lcols).unwrap(),
+            (vec![Term::Val(0), Term::Val(1).negate()], vec![Term::Val(1), Term::Val(0)]));
+    }
+
+    #[test]
+    fn equal_literal() {
+        let rcols = "memo, price:Decimal".parse::<Columns>().unwrap();
+        let lcols = "Memo Text, amount:Decimal".parse::<Columns>().unwrap();
+        let (rterms, lterms) = parse_equal(r#"(memo, "a,b=(c)", price - 1) = (`Memo Text`, "a,b=(c)", amount)"#, &rcols, &lcols).unwrap();
+        assert_eq!(rterms, vec![
+            Term::Val(0),
+            Term::Lit(Value::String("a,b=(c)".to_owned())),
+            Term::Sum(vec![Term::Val(1), Term::Lit(Value::Decimal((-1).into()))]),
+        ]);
+        assert_eq!(lterms[0], Term::Val(0));
+        assert_eq!(rterms[2].describe(&rcols), "price - 1");
+        assert_eq!(rterms[1].describe(&rcols), r#""a,b=(c)""#);
+    }
+
+    #[test]
+    fn equal_types() {
+        let rcols = "d:Date, n:Integer".parse::<Columns>().unwrap();
+        let lcols = "q:Integer, e:Date".parse::<Columns>().unwrap();
+
+        let err = parse_equal("(d) = (q)", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::PositionTypeMismatch {
+            position: 1,
+            left: "Date".to_owned(),
+            right: "Integer".to_owned(),
+        });
+        let err = parse_equal("(d, n) = (e, e)", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.to_string(), "\
+the value at position 2 is Integer on the left side, but Date on the right side at column 5
+  (d, n) = (e, e)
+      ^^^^^^^^^^");
+
+        let (rterms, lterms) = parse_equal(r#"(d, 1) = ("2020-09-12", q)"#, &rcols, &lcols).unwrap();
+        assert_eq!(rterms[1], Term::Lit(Value::Integer(1)));
+        assert_eq!(lterms[0], Term::Lit(Value::Date(NaiveDate::from_ymd(2020, 9, 12))));
+        assert_eq!(lterms[0].ty(&lcols), Type::Date);
+        assert_eq!(rterms[1].ty(&rcols), Type::Integer);
+        assert_eq!(Term::Lit(Value::Bool(true)).ty(&rcols), Type::Bool);
+        assert_eq!(Term::Lit(Value::Time(chrono::NaiveTime::from_hms(9, 0, 0))).ty(&rcols), Type::Time);
+        assert_eq!(Term::Lit(Value::DateTime(NaiveDate::from_ymd(2020, 9, 12).and_hms(9, 0, 0))).ty(&rcols), Type::DateTime);
+    }
+
+    #[test]
+    fn errors() {
+        let rcols = "date:Date, memo, price:Decimal".parse::<Columns>().unwrap();
+        let lcols = "date:Date, amount:Decimal".parse::<Columns>().unwrap();
+
+        let err = parse_equal("(date, price + memo) = (date, amount)", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.column(), 16);
+        assert_eq!(err.to_string(), "\
+`memo` is String, but a decimal for addition is expected at column 16
+  (date, price + memo) = (date, amount)
+                 ^^^^");
+
+        let err = parse_equal("(date, prise) = (date, amount)", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UndefinedField("prise".to_owned()));
+        assert_eq!(err.column(), 8);
+
+        let err = parse_equal("(date, price) = date", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::LengthMismatch { left: 2, right: 1 });
+
+        assert!(parse_equal("((date, price)) = ((date, amount))", &rcols, &lcols).is_ok());
+        let err = parse_equal("(date, (price, memo)) = (date, amount, amount)", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::NestedTuple("nested".to_owned()));
+
+        let err = parse_sum_equal("-date = amount", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.to_string().lines().next().unwrap(),
+            "`date` is Date, but a decimal or an integer for negation is expected at column 2");
+
+        let err = parse_approx("price ~ amount within 0.0.1", &rcols, &lcols).unwrap_err();
+        assert_eq!(err.column(), 23);
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        let cols = "qty:Integer, price:Decimal".parse::<Columns>().unwrap();
+        let err = parse_term("price + qty + 1", &cols).unwrap_err();
+        assert_eq!(err.to_string(), "\
+`qty` is Integer, but integer arithmetic is not supported at column 9
+  price + qty + 1
+          ^^^");
+        let err = parse_term("qty / 2", &cols).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::IntegerArithmetic("qty".to_owned()));
+        let err = parse_term("price * -abs(qty)", &cols).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::IntegerArithmetic("-abs(qty)".to_owned()));
+
+        assert_eq!(parse_term("-qty", &cols).unwrap().ty(&cols), Type::Integer);
+        assert_eq!(parse_term("abs(qty)", &cols).unwrap().ty(&cols), Type::Integer);
+    }
+
+    #[test]
+    fn approx() {
+        let rcols = "date:Date, price:Decimal".parse::<Columns>().unwrap();
+        let lcols = "date:Date, amount:Decimal".parse::<Columns>().unwrap();
+        let approx = parse_approx("price ~ -amount within 0.01", &rcols, &lcols).unwrap();
+        assert_eq!(approx.name(), "price");
+
+        let date = Value::Date(chrono::NaiveDate::from_ymd(2020, 9, 12));
+        let source = vec![date.clone(), Value::Decimal("10.00".parse().unwrap())];
+        let near = vec![date.clone(), Value::Decimal("-10.01".parse().unwrap())];
+        let far = vec![date, Value::Decimal("-10.02".parse().unwrap())];
+        assert_eq!(approx.delta(&source, &near), Some("0.01".parse().unwrap()));
+        assert_eq!(approx.delta(&source, &far), None);
+        assert_eq!(approx.describe_delta("0.01".parse().unwrap()), "+0.01");
+
+        assert!(parse_approx("date ~ date within 0.01", &rcols, &lcols).is_err());
+        assert!(parse_approx("price ~ amount", &rcols, &lcols).is_err());
+    }
+
+    #[test]
+    fn approx_days() {
+        let cols = "date:Date, price:Decimal".parse::<Columns>().unwrap();
+        let approx = parse_approx("date ~ date within 3 days", &cols, &cols).unwrap();
+        assert_eq!(approx.tolerance, Tolerance::Days(3));
+
+        let date = |d| vec![Value::Date(chrono::NaiveDate::from_ymd(2020, 9, d)), Value::Decimal(100.into())];
+        assert_eq!(approx.delta(&date(12), &date(15)), Some(3.into()));
+        assert_eq!(approx.delta(&date(12), &date(16)), None);
+        assert_eq!(approx.describe_delta(2.into()), "+2 days");
+        assert_eq!(approx.describe_delta((-1).into()), "-1 day");
+
+        assert!(parse_approx("price ~ price within 3 days", &cols, &cols).is_err());
+        assert!(parse_approx("date ~ date within a few days", &cols, &cols).is_err());
+    }
+
+    #[test]
+    fn sum_equal() {
+        let rcols = "date:Date, price:Decimal".parse::<Columns>().unwrap();
+        let lcols = "date:Date, memo, amount:Decimal".parse::<Columns>().unwrap();
+        let sum = parse_sum_equal("-price = amount", &rcols, &lcols).unwrap();
+        assert_eq!(sum.name(), "-price");
+
+        let records: Vec<Record> = vec![
+            vec![Value::Null, Value::Decimal((-100).into())].into(),
+            vec![Value::Null, Value::Decimal((-100).into())].into(),
+            vec![Value::Null, Value::Null].into(),
+        ];
+        assert_eq!(sum.source_sum(&records), 200.into());
+
+        assert!(parse_sum_equal("price = memo", &rcols, &lcols).is_err());
     }
 }
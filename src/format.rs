@@ -0,0 +1,544 @@
+use rust_decimal::Decimal;
+use chrono::{NaiveDate, NaiveTime, NaiveDateTime, DateTime, FixedOffset};
+use thiserror::Error;
+
+#[derive(Error,Debug)]
+pub enum ParseError {
+    #[error("No date format is given")]
+    EmptyDateFormat,
+    #[error("Invalid timezone offset: {0}")]
+    InvalidOffset(String),
+    #[error("Unknown option: {0}")]
+    UnknownOption(String),
+    #[error("Option `{0}` requires a value")]
+    MissingValue(String),
+    #[error("Option `{0}` requires a single character")]
+    NotCharacter(String),
+    #[error("A closing quote is missing")]
+    MissingCloseQuote,
+    #[error("The grouping separator and the decimal mark must be different")]
+    SameSeparators,
+}
+
+#[derive(Error,Debug)]
+pub enum TemporalError {
+    #[error("Field value does not match any format")]
+    Mismatch,
+    #[error("Field value matches several formats with different values")]
+    Ambiguous,
+}
+
+#[derive(Error,Debug)]
+pub enum DecimalError {
+    #[error("Field value is not a decimal")]
+    Decimal(#[from] rust_decimal::Error),
+    #[error("Field value has more than one negative sign")]
+    MultipleSigns,
+    #[error("Field value is empty")]
+    Empty,
+    #[error("Grouping separators must split the integer part into groups of three digits")]
+    MisplacedGroup,
+}
+
+/// Candidate formats for parsing dates
+///
+/// A field is parsed with all formats and must not give different dates.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct DateFormat(Vec<String>);
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat(vec![
+            "%Y-%m-%d".to_owned(),
+            "%Y/%m/%d".to_owned(),
+            "%Y年%m月%d日".to_owned(),
+        ])
+    }
+}
+
+impl std::str::FromStr for DateFormat {
+    type Err = self::ParseError;
+
+    /// Parses formats separated by `|`
+    fn from_str(s: &str) -> Result<DateFormat, Self::Err> {
+        parse_formats(s).map(DateFormat)
+    }
+}
+
+impl DateFormat {
+    pub fn iter(&self) -> impl Iterator<Item=&str> {
+        self.0.iter().map(|s| s.as_str())
+    }
+
+    pub fn parse(&self, field: &str) -> Result<NaiveDate, TemporalError> {
+        parse_unique(&self.0, |fmt| NaiveDate::parse_from_str(field.trim(), fmt).ok())
+    }
+}
+
+/// Candidate formats for parsing times of day
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct TimeFormat(Vec<String>);
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        TimeFormat(vec![
+            "%H:%M:%S%.f".to_owned(),
+            "%H:%M".to_owned(),
+        ])
+    }
+}
+
+impl std::str::FromStr for TimeFormat {
+    type Err = self::ParseError;
+
+    /// Parses formats separated by `|`
+    fn from_str(s: &str) -> Result<TimeFormat, Self::Err> {
+        parse_formats(s).map(TimeFormat)
+    }
+}
+
+impl TimeFormat {
+    pub fn parse(&self, field: &str) -> Result<NaiveTime, TemporalError> {
+        parse_unique(&self.0, |fmt| NaiveTime::parse_from_str(field.trim(), fmt).ok())
+    }
+}
+
+/// Candidate formats for parsing date and time
+///
+/// Values are normalized to UTC.
+/// A value without a timezone offset is assumed to be in the default offset, which is UTC unless specified.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct DateTimeFormat {
+    formats: Vec<String>,
+    offset: FixedOffset,
+}
+
+impl Default for DateTimeFormat {
+    fn default() -> Self {
+        DateTimeFormat {
+            formats: vec![
+                "%Y-%m-%dT%H:%M:%S%.f%:z".to_owned(),
+                "%Y-%m-%d %H:%M:%S%.f%:z".to_owned(),
+                "%Y-%m-%dT%H:%M:%S%.f".to_owned(),
+                "%Y-%m-%d %H:%M:%S%.f".to_owned(),
+                "%Y/%m/%d %H:%M:%S%.f".to_owned(),
+            ],
+            offset: FixedOffset::east(0),
+        }
+    }
+}
+
+impl std::str::FromStr for DateTimeFormat {
+    type Err = self::ParseError;
+
+    /// Parses formats separated by `|`
+    ///
+    /// An item `tz=+09:00` sets the default offset.
+    /// If no format is given, the default formats are used.
+    fn from_str(s: &str) -> Result<DateTimeFormat, Self::Err> {
+        let mut offset = None;
+        let mut formats = Vec::new();
+        for item in s.split('|').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match item.strip_prefix("tz=") {
+                Some(tz) => offset = Some(parse_offset(tz.trim())?),
+                None => formats.push(item.to_owned()),
+            }
+        }
+
+        let default = DateTimeFormat::default();
+        match (formats.is_empty(), offset) {
+            (true, None) => Err(ParseError::EmptyDateFormat),
+            (true, Some(offset)) => Ok(DateTimeFormat { offset, ..default }),
+            (false, offset) => Ok(DateTimeFormat {
+                formats,
+                offset: offset.unwrap_or(default.offset),
+            }),
+        }
+    }
+}
+
+impl DateTimeFormat {
+    /// Parses a date and time and converts it into UTC
+    pub fn parse(&self, field: &str) -> Result<NaiveDateTime, TemporalError> {
+        let field = field.trim();
+        parse_unique(&self.formats, |fmt| {
+            if has_offset(fmt) {
+                let field = match field.strip_suffix('Z') {
+                    Some(rest) => std::borrow::Cow::Owned(format!("{}+00:00", rest)),
+                    None => std::borrow::Cow::Borrowed(field),
+                };
+                DateTime::parse_from_str(&field, fmt)
+                    .ok()
+                    .map(|dt| dt.naive_utc())
+            } else {
+                NaiveDateTime::parse_from_str(field, fmt)
+                    .ok()
+                    .map(|dt| dt - self.offset)
+            }
+        })
+    }
+}
+
+fn has_offset(fmt: &str) -> bool {
+    fmt.contains("%z") || fmt.contains("%:z") || fmt.contains("%#z")
+}
+
+/// Parses an offset such as `+09:00`, `-0500` or `Z`
+fn parse_offset(s: &str) -> Result<FixedOffset, ParseError> {
+    let invalid = || ParseError::InvalidOffset(s.to_owned());
+    if s == "Z" {
+        return Ok(FixedOffset::east(0));
+    }
+
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(invalid()),
+    };
+    let digits: String = rest.chars().filter(|c| *c != ':').collect();
+    if !(digits.len() == 2 || digits.len() == 4) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let hours: i32 = digits[..2].parse().map_err(|_| invalid())?;
+    let minutes: i32 = if digits.len() == 4 { digits[2..].parse().map_err(|_| invalid())? } else { 0 };
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+        .ok_or_else(invalid)
+}
+
+fn parse_formats(s: &str) -> Result<Vec<String>, ParseError> {
+    let formats: Vec<String> = s.split('|')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect();
+
+    if formats.is_empty() {
+        Err(ParseError::EmptyDateFormat)
+    } else {
+        Ok(formats)
+    }
+}
+
+/// Parses a value with all formats and checks that the results agree
+fn parse_unique<T, F>(formats: &[String], f: F) -> Result<T, TemporalError>
+where
+    T: PartialEq,
+    F: Fn(&str) -> Option<T>,
+{
+    let mut values = formats.iter().filter_map(|fmt| f(fmt));
+    let value = values.next().ok_or(TemporalError::Mismatch)?;
+    if values.any(|other| other != value) {
+        return Err(TemporalError::Ambiguous);
+    }
+    Ok(value)
+}
+
+/// Notation of decimal numbers
+///
+/// The default format accepts only plain numbers such as `-1000.5`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct DecimalFormat {
+    group: Option<char>,
+    point: char,
+    prefixes: Vec<String>,
+    suffixes: Vec<String>,
+    parens: bool,
+    trailing_minus: bool,
+    empty_as_zero: bool,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        DecimalFormat {
+            group: None,
+            point: '.',
+            prefixes: Vec::new(),
+            suffixes: Vec::new(),
+            parens: false,
+            trailing_minus: false,
+            empty_as_zero: false,
+        }
+    }
+}
+
+impl std::str::FromStr for DecimalFormat {
+    type Err = self::ParseError;
+
+    /// Parses options separated by commas
+    ///
+    /// - `group=','`: grouping separator between groups of three digits
+    /// - `point='.'`: decimal mark
+    /// - `prefix='¥|$'`: currency symbols allowed before a number
+    /// - `suffix='円'`: currency symbols allowed after a number
+    /// - `parens`: a number in parentheses is negative
+    /// - `trailing_minus`: a number followed by `-` is negative
+    /// - `empty_as_zero`: an empty field is zero
+    ///
+    /// Values may be quoted by single quotes.
+    fn from_str(s: &str) -> Result<DecimalFormat, Self::Err> {
+        let mut format = DecimalFormat::default();
+
+        for option in split_options(s)? {
+            let (key, value) = match option.find('=') {
+                Some(pos) => (option[..pos].trim(), Some(unquote(option[pos+1..].trim()))),
+                None => (option.trim(), None),
+            };
+            let require = |value: Option<&str>| value.map(|v| v.to_owned())
+                .ok_or_else(|| ParseError::MissingValue(key.to_owned()));
+
+            match key {
+                "group" => format.group = Some(single_char(key, &require(value)?)?),
+                "point" => format.point = single_char(key, &require(value)?)?,
+                "prefix" => format.prefixes = split_alternatives(&require(value)?),
+                "suffix" => format.suffixes = split_alternatives(&require(value)?),
+                "parens" => format.parens = true,
+                "trailing_minus" => format.trailing_minus = true,
+                "empty_as_zero" => format.empty_as_zero = true,
+                _ => return Err(ParseError::UnknownOption(key.to_owned())),
+            }
+        }
+
+        if format.group == Some(format.point) {
+            return Err(ParseError::SameSeparators);
+        }
+
+        Ok(format)
+    }
+}
+
+impl DecimalFormat {
+    fn strip_parens<'s>(&self, s: &'s str) -> Option<&'s str> {
+        if self.parens {
+            s.strip_prefix('(')
+                .and_then(|s| s.strip_suffix(')'))
+                .map(|s| s.trim())
+        } else {
+            None
+        }
+    }
+
+    pub fn parse(&self, field: &str) -> Result<Decimal, DecimalError> {
+        fn set_negative(negative: &mut bool) -> Result<(), DecimalError> {
+            if *negative {
+                Err(DecimalError::MultipleSigns)
+            } else {
+                *negative = true;
+                Ok(())
+            }
+        }
+
+        let mut s = field.trim();
+        let mut negative = false;
+
+        if s.is_empty() && self.empty_as_zero {
+            return Ok(Decimal::new(0, 0));
+        }
+
+        // Parentheses may enclose currency symbols like `(¥1,500)` or be enclosed like `¥(1,500)`
+        if let Some(rest) = self.strip_parens(s) {
+            set_negative(&mut negative)?;
+            s = rest;
+        }
+        if self.trailing_minus {
+            if let Some(rest) = s.strip_suffix('-') {
+                set_negative(&mut negative)?;
+                s = rest.trim();
+            }
+        }
+        if let Some(rest) = s.strip_prefix('-') {
+            set_negative(&mut negative)?;
+            s = rest.trim();
+        }
+
+        if let Some(rest) = self.prefixes.iter().find_map(|p| s.strip_prefix(p.as_str())) {
+            s = rest.trim();
+        }
+        if let Some(rest) = self.suffixes.iter().find_map(|p| s.strip_suffix(p.as_str())) {
+            s = rest.trim();
+        }
+        if let Some(rest) = self.strip_parens(s) {
+            set_negative(&mut negative)?;
+            s = rest;
+        }
+        if let Some(rest) = s.strip_prefix('-') {
+            set_negative(&mut negative)?;
+            s = rest.trim();
+        }
+
+        if s.is_empty() {
+            return Err(DecimalError::Empty);
+        }
+
+        if let Some(group) = self.group {
+            check_groups(s, group, self.point)?;
+        }
+
+        let normalized: String = s.chars()
+            .filter(|c| Some(*c) != self.group)
+            .map(|c| if c == self.point { '.' } else { c })
+            .collect();
+        let value: Decimal = normalized.parse()?;
+
+        Ok(if negative { -value } else { value })
+    }
+}
+
+/// Checks that grouping separators appear only between groups of three digits in the integer part
+fn check_groups(s: &str, group: char, point: char) -> Result<(), DecimalError> {
+    let (integer, fraction) = match s.find(point) {
+        Some(pos) => (&s[..pos], &s[pos..]),
+        None => (s, ""),
+    };
+    if fraction.contains(group) {
+        return Err(DecimalError::MisplacedGroup);
+    }
+    if !integer.contains(group) {
+        return Ok(());
+    }
+
+    let mut groups = integer.split(group);
+    let first = groups.next().map_or(0, |g| g.chars().count());
+    if first == 0 || first > 3 || groups.any(|g| g.chars().count() != 3) {
+        return Err(DecimalError::MisplacedGroup);
+    }
+    Ok(())
+}
+
+/// Splits options by commas outside of single quotes
+fn split_options(s: &str) -> Result<Vec<&str>, ParseError> {
+    let mut options = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (pos, c) in s.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            ',' if !quoted => {
+                options.push(&s[start..pos]);
+                start = pos + 1;
+            },
+            _ => {},
+        }
+    }
+    if quoted {
+        return Err(ParseError::MissingCloseQuote);
+    }
+    options.push(&s[start..]);
+
+    Ok(options.into_iter()
+        .filter(|s| !s.trim().is_empty())
+        .collect())
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .unwrap_or(s)
+}
+
+fn single_char(key: &str, value: &str) -> Result<char, ParseError> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(ParseError::NotCharacter(key.to_owned())),
+    }
+}
+
+fn split_alternatives(s: &str) -> Vec<String> {
+    s.split('|')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dec(n: i64, scale: u32) -> Decimal {
+        Decimal::new(n, scale)
+    }
+
+    #[test]
+    fn parse_datetime() {
+        let utc = NaiveDate::from_ymd(2020, 9, 12).and_hms(1, 0, 0);
+        let format = DateTimeFormat::default();
+        assert_eq!(format.parse("2020-09-12T10:00:00+09:00").unwrap(), utc);
+        assert_eq!(format.parse("2020-09-12T01:00:00Z").unwrap(), utc);
+        assert_eq!(format.parse("2020-09-12 01:00:00").unwrap(), utc);
+        assert!(format.parse("2020-09-12").is_err());
+
+        let format: DateTimeFormat = "%Y/%m/%d %H:%M | tz=+09:00".parse().unwrap();
+        assert_eq!(format.parse("2020/09/12 10:00").unwrap(), utc);
+
+        let format: DateTimeFormat = "tz=-05:30".parse().unwrap();
+        assert_eq!(format.parse("2020-09-11 19:30:00").unwrap(), utc);
+
+        assert!("tz=9".parse::<DateTimeFormat>().is_err());
+        assert!("".parse::<DateTimeFormat>().is_err());
+    }
+
+    #[test]
+    fn parse_time() {
+        let format = TimeFormat::default();
+        assert_eq!(format.parse("10:30").unwrap(), NaiveTime::from_hms(10, 30, 0));
+        assert_eq!(format.parse("10:30:15").unwrap(), NaiveTime::from_hms(10, 30, 15));
+        assert!(format.parse("10").is_err());
+    }
+
+    #[test]
+    fn parse_decimal_format() {
+        let format: DecimalFormat = "group=',', prefix='¥|$', suffix=円, parens, trailing_minus".parse().unwrap();
+        assert_eq!(format, DecimalFormat {
+            group: Some(','),
+            point: '.',
+            prefixes: vec!["¥".to_owned(), "$".to_owned()],
+            suffixes: vec!["円".to_owned()],
+            parens: true,
+            trailing_minus: true,
+            empty_as_zero: false,
+        });
+
+        assert!("group='.', point='.'".parse::<DecimalFormat>().is_err());
+        assert!("group=''".parse::<DecimalFormat>().is_err());
+        assert!("group".parse::<DecimalFormat>().is_err());
+        assert!("unknown".parse::<DecimalFormat>().is_err());
+        assert!("group=','', parens".parse::<DecimalFormat>().is_err());
+    }
+
+    #[test]
+    fn parse_decimal() {
+        let format: DecimalFormat = "group=',', prefix='¥', suffix='円', parens, trailing_minus".parse().unwrap();
+        assert_eq!(format.parse("1,000").unwrap(), dec(1000, 0));
+        assert_eq!(format.parse("¥1,000").unwrap(), dec(1000, 0));
+        assert_eq!(format.parse("(1,500)").unwrap(), dec(-1500, 0));
+        assert_eq!(format.parse("-1,000円").unwrap(), dec(-1000, 0));
+        assert_eq!(format.parse("¥-1,000").unwrap(), dec(-1000, 0));
+        assert_eq!(format.parse("1,000-").unwrap(), dec(-1000, 0));
+        assert!(format.parse("(-1,000)").is_err());
+        assert_eq!(format.parse("¥(1,500)").unwrap(), dec(-1500, 0));
+        assert_eq!(format.parse("(1,500)円").unwrap(), dec(-1500, 0));
+        assert!(format.parse("(¥(1,500))").is_err());
+        assert_eq!(format.parse("1,234,567").unwrap(), dec(1234567, 0));
+        assert!(format.parse("1,0,0").is_err());
+        assert!(format.parse("1000,000").is_err());
+        assert!(format.parse(",100").is_err());
+        assert!(format.parse("100,").is_err());
+        assert!(format.parse("¥").is_err());
+        assert!(format.parse("").is_err());
+
+        let format: DecimalFormat = "empty_as_zero".parse().unwrap();
+        assert_eq!(format.parse(" ").unwrap(), dec(0, 0));
+
+        let format: DecimalFormat = "group='.', point=','".parse().unwrap();
+        assert_eq!(format.parse("1.234,56").unwrap(), dec(123456, 2));
+        assert_eq!(format.parse("1234,56").unwrap(), dec(123456, 2));
+        assert!(format.parse("1.234,5.6").is_err());
+
+        let format = DecimalFormat::default();
+        assert_eq!(format.parse("-1000.5").unwrap(), dec(-10005, 1));
+        assert!(format.parse("1,000").is_err());
+        assert!(format.parse("(1000)").is_err());
+    }
+}
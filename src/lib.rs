@@ -1,35 +1,149 @@
-use strum_macros::EnumString;
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveTime, NaiveDateTime};
 use rust_decimal::prelude::*;
+use thiserror::Error;
+use crate::format::{DateFormat, TimeFormat, DateTimeFormat, DecimalFormat};
 
 pub mod app;
 pub mod core;
 pub mod columns;
+pub mod encoding;
 pub mod expr;
+pub mod format;
+pub mod output;
+pub mod parser;
 pub mod table;
 pub mod record;
 pub mod quotient;
 
-#[derive(Debug,Clone,Copy,PartialEq,EnumString)]
+#[derive(Error,Debug)]
+pub enum ParseTagError {
+    #[error("Unknown type: {0}")]
+    UnknownType(String),
+    #[error("Type `{0}` does not take arguments")]
+    UnexpectedArguments(String),
+    #[error("A closing parenthesis is missing")]
+    MissingCloseParen,
+    #[error("Invalid format")]
+    Format(#[from] crate::format::ParseError),
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum Tag {
     String,
-    Decimal,
-    Date,
+    Decimal(DecimalFormat),
+    Date(DateFormat),
+    Integer,
+    Bool,
+    Time(TimeFormat),
+    /// Date and time normalized to UTC
+    DateTime(DateTimeFormat),
+}
+
+impl std::str::FromStr for Tag {
+    type Err = ParseTagError;
+
+    /// Parses a type name optionally followed by arguments in parentheses
+    ///
+    /// e.g. `String`, `Decimal(group=',')`, `Date`, `Date(%m/%d/%Y)`, `DateTime(tz=+09:00)`
+    fn from_str(s: &str) -> Result<Tag, Self::Err> {
+        let s = s.trim();
+        let (name, args) = match s.find('(') {
+            Some(pos) => {
+                let args = s[pos+1..].strip_suffix(')')
+                    .ok_or(ParseTagError::MissingCloseParen)?;
+                (s[..pos].trim(), Some(args))
+            },
+            None => (s, None),
+        };
+
+        match (name, args) {
+            ("String", None) => Ok(Tag::String),
+            ("Decimal", None) => Ok(Tag::Decimal(DecimalFormat::default())),
+            ("Decimal", Some(args)) => Ok(Tag::Decimal(args.parse()?)),
+            ("Date", None) => Ok(Tag::Date(DateFormat::default())),
+            ("Date", Some(args)) => Ok(Tag::Date(args.parse()?)),
+            ("Integer", None) => Ok(Tag::Integer),
+            ("Bool", None) => Ok(Tag::Bool),
+            ("Time", None) => Ok(Tag::Time(TimeFormat::default())),
+            ("Time", Some(args)) => Ok(Tag::Time(args.parse()?)),
+            ("DateTime", None) => Ok(Tag::DateTime(DateTimeFormat::default())),
+            ("DateTime", Some(args)) => Ok(Tag::DateTime(args.parse()?)),
+            ("String", Some(_)) | ("Integer", Some(_)) | ("Bool", Some(_)) => Err(ParseTagError::UnexpectedArguments(name.to_owned())),
+            _ => Err(ParseTagError::UnknownType(name.to_owned())),
+        }
+    }
 }
 
 #[derive(Debug,Clone,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub enum Value {
+    /// Missing value, which is less than any other value
+    Null,
     String(String),
     Decimal(Decimal),
     Date(NaiveDate),
+    Integer(i64),
+    Bool(bool),
+    Time(NaiveTime),
+    /// Date and time in UTC
+    DateTime(NaiveDateTime),
 }
 
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            Value::Null => Ok(()),
             Value::String(s) => write!(f, "{}", s),
             Value::Decimal(n) => write!(f, "{}", n),
             Value::Date(d) => write!(f, "{}", d),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Time(t) => write!(f, "{}", t),
+            Value::DateTime(dt) => write!(f, "{}Z", dt.format("%Y-%m-%dT%H:%M:%S%.f")),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_tag() {
+        assert_eq!("String".parse::<Tag>().unwrap(), Tag::String);
+        assert_eq!("Decimal".parse::<Tag>().unwrap(), Tag::Decimal(DecimalFormat::default()));
+        assert_eq!("Decimal(group=',', parens)".parse::<Tag>().unwrap(),
+            Tag::Decimal("group=',', parens".parse().unwrap()));
+        assert_eq!("Date".parse::<Tag>().unwrap(), Tag::Date(DateFormat::default()));
+        assert_eq!("Date(%m/%d/%Y)".parse::<Tag>().unwrap(),
+            Tag::Date("%m/%d/%Y".parse().unwrap()));
+        assert_eq!("Date(%d.%m.%Y | %Y%m%d)".parse::<Tag>().unwrap(),
+            Tag::Date("%d.%m.%Y|%Y%m%d".parse().unwrap()));
+        assert!("Date(%m/%d/%Y".parse::<Tag>().is_err());
+        assert!("Date()".parse::<Tag>().is_err());
+        assert!("Decimal(2)".parse::<Tag>().is_err());
+        assert!("String(2)".parse::<Tag>().is_err());
+        assert_eq!("Integer".parse::<Tag>().unwrap(), Tag::Integer);
+        assert_eq!("Bool".parse::<Tag>().unwrap(), Tag::Bool);
+        assert_eq!("Time".parse::<Tag>().unwrap(), Tag::Time(TimeFormat::default()));
+        assert_eq!("DateTime(%Y/%m/%d %H:%M|tz=+09:00)".parse::<Tag>().unwrap(),
+            Tag::DateTime("%Y/%m/%d %H:%M|tz=+09:00".parse().unwrap()));
+        assert!("Integer(8)".parse::<Tag>().is_err());
+        assert!("Float".parse::<Tag>().is_err());
+    }
+
+    #[test]
+    fn null() {
+        assert!(Value::Null < Value::String(String::new()));
+        assert!(Value::Null < Value::Decimal((-1).into()));
+        assert_eq!(Value::Null.to_string(), "");
+    }
+
+    #[test]
+    fn display() {
+        let dt = NaiveDate::from_ymd(2020, 9, 12).and_hms(1, 0, 0);
+        assert_eq!(Value::DateTime(dt).to_string(), "2020-09-12T01:00:00Z");
+        assert_eq!(Value::Time(NaiveTime::from_hms(10, 30, 0)).to_string(), "10:30:00");
+        assert_eq!(Value::Bool(true).to_string(), "true");
+        assert_eq!(Value::Integer(-3).to_string(), "-3");
+    }
+}
@@ -1,25 +1,76 @@
+use std::fs::File;
+use std::process;
 use structopt::StructOpt;
+use color_eyre::eyre::{Result, WrapErr};
 use compare_csv::app::App;
 use compare_csv::core::TableComparator;
-use owo_colors::OwoColorize;
+use compare_csv::output::{self, OutputFormat};
 
-fn main() -> color_eyre::eyre::Result<()> {
-    color_eyre::install()?;
+const EXIT_SAME: i32 = 0;
+const EXIT_DIFFERENT: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+fn main() {
+    if let Err(e) = color_eyre::install() {
+        eprintln!("Error: {:?}", e);
+        process::exit(EXIT_ERROR);
+    }
     env_logger::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 
-    let app = App::from_args();
-    let comp = TableComparator::from_app(&app)?;
+    let app = match App::from_iter_safe(std::env::args_os()) {
+        Ok(app) => app,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            process::exit(EXIT_ERROR);
+        },
+        Err(e) => e.exit(),
+    };
 
-    for p in comp.different_points().iter() {
-        println!("{}", format!("# {}", p).bold());
-        let (src, dst) = comp.get_records(p);
-        for x in src.iter() {
-            println!("{}", format!("< {}", x).red());
-        }
-        for x in dst.iter() {
-            println!("{}", format!("> {}", x).green());
+    match run(&app) {
+        Ok(true) => process::exit(EXIT_SAME),
+        Ok(false) => process::exit(EXIT_DIFFERENT),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            process::exit(EXIT_ERROR);
+        },
+    }
+}
+
+/// Compares the tables and returns `true` if they are same
+fn run(app: &App) -> Result<bool> {
+    let comp = TableComparator::from_app(app)?;
+
+    if !app.quiet() {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        match app.format() {
+            OutputFormat::Plain => {
+                output::write_plain(&comp, &mut stdout, app.color().enabled(), app.full_classes())?;
+                if app.summary() {
+                    output::write_summary(&comp.summary(), &mut stdout)?;
+                }
+            },
+            OutputFormat::Json => output::write_json(&comp, &mut stdout, app.full_classes())?,
+            OutputFormat::JsonLines => output::write_json_lines(&comp, &mut stdout, app.full_classes())?,
         }
     }
 
-    Ok(())
+    if let Some(path) = app.source_only_out() {
+        let f = File::create(path).wrap_err("Failed to create a file for source-only records")?;
+        output::write_source_only(&comp, f).wrap_err("Failed to write source-only records")?;
+    }
+    if let Some(path) = app.target_only_out() {
+        let f = File::create(path).wrap_err("Failed to create a file for target-only records")?;
+        output::write_target_only(&comp, f).wrap_err("Failed to write target-only records")?;
+    }
+    if let Some(path) = app.matched_out() {
+        let f = File::create(path).wrap_err("Failed to create a file for matched records")?;
+        output::write_matched(&comp, f).wrap_err("Failed to write matched records")?;
+    }
+    if let Some(path) = app.target_matched_out() {
+        let f = File::create(path).wrap_err("Failed to create a file for matched target records")?;
+        output::write_target_matched(&comp, f).wrap_err("Failed to write matched target records")?;
+    }
+
+    Ok(comp.is_same())
 }
@@ -0,0 +1,686 @@
+use std::io::Write;
+use csv::StringRecord;
+use owo_colors::OwoColorize;
+use serde_json::{json, Map};
+use thiserror::Error;
+use crate::Value;
+use crate::columns::Columns;
+use crate::core::{TableComparator, Partition, Change, ApproxMatch, SubsetMatch, GroupSums, Summary, SideSummary};
+use crate::record::Record;
+use crate::table::CsvFormat;
+
+#[derive(Error,Debug)]
+pub enum ParseError {
+    #[error("Unknown output format: {0}")]
+    UnknownFormat(String),
+    #[error("Unknown color choice: {0}")]
+    UnknownColorChoice(String),
+}
+
+/// Format of comparison results
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum OutputFormat {
+    /// `# point`, `< source record` and `> target record` lines
+    Plain,
+    /// A JSON document
+    Json,
+    /// JSON Lines, one difference per line followed by a summary
+    JsonLines,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = self::ParseError;
+
+    fn from_str(s: &str) -> Result<OutputFormat, Self::Err> {
+        match s {
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            _ => Err(ParseError::UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+/// When to colorize the plain output
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ColorChoice {
+    /// Colorize if stdout is a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = self::ParseError;
+
+    fn from_str(s: &str) -> Result<ColorChoice, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(ParseError::UnknownColorChoice(s.to_owned())),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Decides whether to colorize the output written to stdout
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = std::env::var_os("NO_COLOR")
+                    .map(|v| !v.is_empty())
+                    .unwrap_or(false);
+                !no_color && atty::is(atty::Stream::Stdout)
+            },
+        }
+    }
+}
+
+/// Returns surplus records of a point, or all records if `full_classes` is set
+fn records_to_show<'a>(comp: &'a TableComparator, point: &Record, full_classes: bool) -> (&'a [Record], &'a [Record]) {
+    if full_classes {
+        comp.get_records(point)
+    } else {
+        let part = comp.partition(point);
+        (part.source_only, part.target_only)
+    }
+}
+
+/// Describes the numbers of records of a point like `source has 3, target has 2: 1 extra on source`
+pub fn describe_counts(part: &Partition) -> String {
+    let mut extras = Vec::new();
+    if !part.source_only.is_empty() {
+        extras.push(format!("{} extra on source", part.source_only.len()));
+    }
+    if !part.target_only.is_empty() {
+        extras.push(format!("{} extra on target", part.target_only.len()));
+    }
+
+    format!("source has {}, target has {}: {}",
+        part.source_matched.len() + part.source_only.len(),
+        part.target_matched.len() + part.target_only.len(),
+        extras.join(", "))
+}
+
+/// Describes the sums of a group like `sum of price: source 200, target 100`
+pub fn describe_sums(comp: &TableComparator, sums: &GroupSums) -> String {
+    let name = comp.sum_equal().map(|s| s.name()).unwrap_or_default();
+    format!("sum of {}: source {}, target {}", name, sums.source, sums.target)
+}
+
+/// Writes differences in ascending order of points
+///
+/// Each point is followed by the numbers of its records and its surplus records.
+/// If `full_classes` is set, all records of the point are written instead of surpluses.
+/// Records of each side are written in the order of their appearance.
+pub fn write_plain<W: Write>(comp: &TableComparator, w: &mut W, color: bool, full_classes: bool) -> std::io::Result<()> {
+    if comp.key_split().is_some() {
+        write_changes_plain(comp, w, color)?;
+    } else {
+        write_differences_plain(comp, w, color, full_classes)?;
+    }
+    write_approximate_matches_plain(comp, w, color)?;
+    write_subset_matches_plain(comp, w, color)
+}
+
+/// Describes a proposed match like `2 source records for 1 target record`
+pub fn describe_subset(m: &SubsetMatch) -> String {
+    let records = |n: usize| if n == 1 { "record" } else { "records" };
+    format!("{} source {} for {} target {}",
+        m.source.len(), records(m.source.len()),
+        m.target.len(), records(m.target.len()))
+}
+
+/// Writes a line with `style` applied if `color` is set
+fn write_line<W: Write>(w: &mut W, line: String, color: bool, style: fn(&String) -> String) -> std::io::Result<()> {
+    if color {
+        writeln!(w, "{}", style(&line))
+    } else {
+        writeln!(w, "{}", line)
+    }
+}
+
+/// Writes proposed matches as `+ (description)` lines followed by records
+fn write_subset_matches_plain<W: Write>(comp: &TableComparator, w: &mut W, color: bool) -> std::io::Result<()> {
+    for m in comp.subset_matches() {
+        write_line(w, format!("+ ({})", describe_subset(&m)), color, |s| s.cyan().to_string())?;
+        for x in m.source.iter() {
+            write_line(w, format!("< {}", x), color, |s| s.red().to_string())?;
+        }
+        for x in m.target.iter() {
+            write_line(w, format!("> {}", x), color, |s| s.green().to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes differences of an approximate match like `price +0.01`
+pub fn describe_deltas(m: &ApproxMatch) -> String {
+    m.deltas.iter()
+        .map(|(approx, delta)| format!("{} {}", approx.name(), approx.describe_delta(*delta)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes approximate matches as `~ point (differences)` lines followed by records
+fn write_approximate_matches_plain<W: Write>(comp: &TableComparator, w: &mut W, color: bool) -> std::io::Result<()> {
+    for m in comp.approximate_matches() {
+        write_line(w, format!("~ {} ({})", m.point, describe_deltas(&m)), color, |s| s.yellow().to_string())?;
+        write_line(w, format!("< {}", m.source), color, |s| s.red().to_string())?;
+        write_line(w, format!("> {}", m.target), color, |s| s.green().to_string())?;
+    }
+
+    Ok(())
+}
+
+fn write_differences_plain<W: Write>(comp: &TableComparator, w: &mut W, color: bool, full_classes: bool) -> std::io::Result<()> {
+    for p in comp.different_points().iter() {
+        let description = match comp.group_sums(p) {
+            Some(sums) => describe_sums(comp, &sums),
+            None => describe_counts(&comp.partition(p)),
+        };
+        let line = format!("# {} ({})", p, description);
+        if color {
+            writeln!(w, "{}", line.bold())?;
+        } else {
+            writeln!(w, "{}", line)?;
+        }
+        let (src, dst) = records_to_show(comp, p, full_classes);
+        for x in src.iter() {
+            let line = format!("< {}", x);
+            if color {
+                writeln!(w, "{}", line.red())?;
+            } else {
+                writeln!(w, "{}", line)?;
+            }
+        }
+        for x in dst.iter() {
+            let line = format!("> {}", x);
+            if color {
+                writeln!(w, "{}", line.green())?;
+            } else {
+                writeln!(w, "{}", line)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes changes as `# key (change)` lines followed by records
+fn write_changes_plain<W: Write>(comp: &TableComparator, w: &mut W, color: bool) -> std::io::Result<()> {
+    for change in comp.changes() {
+        match change {
+            Change::Removed { key, record } => {
+                write_line(w, format!("# {} (removed)", Record::from(key.to_vec())), color, |s| s.bold().to_string())?;
+                write_line(w, format!("< {}", record), color, |s| s.red().to_string())?;
+            },
+            Change::Added { key, record } => {
+                write_line(w, format!("# {} (added)", Record::from(key.to_vec())), color, |s| s.bold().to_string())?;
+                write_line(w, format!("> {}", record), color, |s| s.green().to_string())?;
+            },
+            Change::Modified { key, source, target, fields } => {
+                let fields: Vec<String> = fields.iter()
+                    .map(|f| format!("{} {} -> {}", f.name, f.source, f.target))
+                    .collect();
+                write_line(w, format!("# {} (modified: {})", Record::from(key.to_vec()), fields.join(", ")), color, |s| s.bold().to_string())?;
+                write_line(w, format!("< {}", source), color, |s| s.red().to_string())?;
+                write_line(w, format!("> {}", target), color, |s| s.green().to_string())?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns differences, or changes if the comparator has a key split
+fn differences_to_json(comp: &TableComparator, full_classes: bool) -> Vec<serde_json::Value> {
+    if comp.key_split().is_some() {
+        comp.changes()
+            .iter()
+            .map(|change| change_to_json(comp, change))
+            .collect()
+    } else {
+        comp.different_points()
+            .into_iter()
+            .map(|p| difference_to_json(comp, p, full_classes))
+            .collect()
+    }
+}
+
+pub fn write_json<W: Write>(comp: &TableComparator, w: &mut W, full_classes: bool) -> std::io::Result<()> {
+    let differences = differences_to_json(comp, full_classes);
+    let approximate_matches: Vec<serde_json::Value> = comp.approximate_matches()
+        .iter()
+        .map(|m| approx_match_to_json(comp, m))
+        .collect();
+    let subset_matches: Vec<serde_json::Value> = comp.subset_matches()
+        .iter()
+        .map(|m| subset_match_to_json(comp, m))
+        .collect();
+    let doc = json!({
+        "differences": differences,
+        "approximate_matches": approximate_matches,
+        "subset_matches": subset_matches,
+        "summary": summary_to_json(comp),
+    });
+
+    serde_json::to_writer_pretty(&mut *w, &doc)?;
+    writeln!(w)
+}
+
+pub fn write_json_lines<W: Write>(comp: &TableComparator, w: &mut W, full_classes: bool) -> std::io::Result<()> {
+    for difference in differences_to_json(comp, full_classes) {
+        serde_json::to_writer(&mut *w, &tagged("difference", difference))?;
+        writeln!(w)?;
+    }
+    for m in comp.approximate_matches() {
+        serde_json::to_writer(&mut *w, &tagged("approximate_match", approx_match_to_json(comp, &m)))?;
+        writeln!(w)?;
+    }
+    for m in comp.subset_matches() {
+        serde_json::to_writer(&mut *w, &tagged("subset_match", subset_match_to_json(comp, &m)))?;
+        writeln!(w)?;
+    }
+
+    serde_json::to_writer(&mut *w, &tagged("summary", summary_to_json(comp)))?;
+    writeln!(w)
+}
+
+/// Prepends `"type": kind` to an object
+fn tagged(kind: &str, value: serde_json::Value) -> serde_json::Value {
+    let mut obj = Map::new();
+    obj.insert("type".to_owned(), json!(kind));
+    if let serde_json::Value::Object(fields) = value {
+        obj.extend(fields);
+    }
+    serde_json::Value::Object(obj)
+}
+
+fn difference_to_json(comp: &TableComparator, point: &Record, full_classes: bool) -> serde_json::Value {
+    let part = comp.partition(point);
+    let (src, dst) = records_to_show(comp, point, full_classes);
+    let mut obj = json!({
+        "key": point.iter().map(value_to_json).collect::<Vec<_>>(),
+        "source_count": part.source_matched.len() + part.source_only.len(),
+        "target_count": part.target_matched.len() + part.target_only.len(),
+        "source_extra": part.source_only.len(),
+        "target_extra": part.target_only.len(),
+        "source": src.iter().map(|r| record_to_json(comp.source().columns(), r)).collect::<Vec<_>>(),
+        "target": dst.iter().map(|r| record_to_json(comp.target().columns(), r)).collect::<Vec<_>>(),
+    });
+    if let (Some(sums), serde_json::Value::Object(fields)) = (comp.group_sums(point), &mut obj) {
+        fields.insert("source_sum".to_owned(), json!(sums.source.to_string()));
+        fields.insert("target_sum".to_owned(), json!(sums.target.to_string()));
+    }
+    obj
+}
+
+fn approx_match_to_json(comp: &TableComparator, m: &ApproxMatch) -> serde_json::Value {
+    let deltas: Map<String, serde_json::Value> = m.deltas.iter()
+        .map(|(approx, delta)| (approx.name().to_owned(), json!(delta.to_string())))
+        .collect();
+    json!({
+        "key": m.point.iter().map(value_to_json).collect::<Vec<_>>(),
+        "source": record_to_json(comp.source().columns(), m.source),
+        "target": record_to_json(comp.target().columns(), m.target),
+        "deltas": deltas,
+    })
+}
+
+fn subset_match_to_json(comp: &TableComparator, m: &SubsetMatch) -> serde_json::Value {
+    json!({
+        "source": m.source.iter().map(|r| record_to_json(comp.source().columns(), r)).collect::<Vec<_>>(),
+        "target": m.target.iter().map(|r| record_to_json(comp.target().columns(), r)).collect::<Vec<_>>(),
+    })
+}
+
+fn change_to_json(comp: &TableComparator, change: &Change) -> serde_json::Value {
+    let key_to_json = |key: &[Value]| key.iter().map(value_to_json).collect::<Vec<_>>();
+    match change {
+        Change::Removed { key, record } => json!({
+            "change": "removed",
+            "key": key_to_json(key),
+            "source": record_to_json(comp.source().columns(), record),
+        }),
+        Change::Added { key, record } => json!({
+            "change": "added",
+            "key": key_to_json(key),
+            "target": record_to_json(comp.target().columns(), record),
+        }),
+        Change::Modified { key, source, target, fields } => json!({
+            "change": "modified",
+            "key": key_to_json(key),
+            "source": record_to_json(comp.source().columns(), source),
+            "target": record_to_json(comp.target().columns(), target),
+            "fields": fields.iter().map(|f| json!({
+                "name": f.name,
+                "source": value_to_json(f.source),
+                "target": value_to_json(f.target),
+            })).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+fn summary_to_json(comp: &TableComparator) -> serde_json::Value {
+    let summary = comp.summary();
+    json!({
+        "source_records": comp.source().num_records(),
+        "target_records": comp.target().num_records(),
+        "differences": comp.different_points().len(),
+        "matched_classes": summary.matched_classes,
+        "count_mismatches": summary.count_mismatches,
+        "approximate_matches": summary.approximate_matches,
+        "subset_matches": summary.subset_matches,
+        "source": side_summary_to_json(&summary.source),
+        "target": side_summary_to_json(&summary.target),
+    })
+}
+
+fn side_summary_to_json(side: &SideSummary) -> serde_json::Value {
+    let totals: Map<String, serde_json::Value> = side.unmatched_totals.iter()
+        .map(|(name, total)| (name.clone(), json!(total.to_string())))
+        .collect();
+    json!({
+        "read": side.stats.read,
+        "skipped_length_mismatch": side.stats.skipped_length_mismatch,
+        "skipped_parse_error": side.stats.skipped_parse_error,
+        "filtered": side.stats.filtered,
+        "records": side.records,
+        "classes": side.classes,
+        "only_classes": side.only_classes,
+        "unmatched_records": side.unmatched_records,
+        "unmatched_totals": totals,
+    })
+}
+
+/// Writes a summary as `name: source value, target value` lines
+pub fn write_summary<W: Write>(summary: &Summary, w: &mut W) -> std::io::Result<()> {
+    let (src, tgt) = (&summary.source, &summary.target);
+    writeln!(w, "## Summary")?;
+    writeln!(w, "records read: source {}, target {}", src.stats.read, tgt.stats.read)?;
+    writeln!(w, "skipped by length mismatch: source {}, target {}",
+        src.stats.skipped_length_mismatch, tgt.stats.skipped_length_mismatch)?;
+    writeln!(w, "skipped by parse error: source {}, target {}",
+        src.stats.skipped_parse_error, tgt.stats.skipped_parse_error)?;
+    writeln!(w, "excluded by filter: source {}, target {}", src.stats.filtered, tgt.stats.filtered)?;
+    writeln!(w, "records compared: source {}, target {}", src.records, tgt.records)?;
+    writeln!(w, "classes: source {}, target {}", src.classes, tgt.classes)?;
+    writeln!(w, "matched classes: {}", summary.matched_classes)?;
+    writeln!(w, "classes only in source: {}", src.only_classes)?;
+    writeln!(w, "classes only in target: {}", tgt.only_classes)?;
+    writeln!(w, "classes with count mismatches: {}", summary.count_mismatches)?;
+    writeln!(w, "approximate matches: {}", summary.approximate_matches)?;
+    writeln!(w, "proposed subset matches: {}", summary.subset_matches)?;
+    writeln!(w, "unmatched records: source {}, target {}", src.unmatched_records, tgt.unmatched_records)?;
+    for (name, total) in src.unmatched_totals.iter() {
+        writeln!(w, "unmatched total of source {}: {}", name, total)?;
+    }
+    for (name, total) in tgt.unmatched_totals.iter() {
+        writeln!(w, "unmatched total of target {}: {}", name, total)?;
+    }
+
+    Ok(())
+}
+
+/// Writes source records which have no counterparts as CSV
+pub fn write_source_only<W: Write>(comp: &TableComparator, w: W) -> csv::Result<()> {
+    let points = comp.different_points();
+    let records = points.iter()
+        .flat_map(|p| comp.partition(p).source_only);
+    write_csv(w, comp.source().format(), comp.source().header(), records)
+}
+
+/// Writes target records which have no counterparts as CSV
+pub fn write_target_only<W: Write>(comp: &TableComparator, w: W) -> csv::Result<()> {
+    let points = comp.different_points();
+    let records = points.iter()
+        .flat_map(|p| comp.partition(p).target_only);
+    write_csv(w, comp.target().format(), comp.target().header(), records)
+}
+
+/// Writes source records which have counterparts as CSV
+pub fn write_matched<W: Write>(comp: &TableComparator, w: W) -> csv::Result<()> {
+    let points = comp.points();
+    let records = points.iter()
+        .flat_map(|p| comp.partition(p).source_matched);
+    write_csv(w, comp.source().format(), comp.source().header(), records)
+}
+
+/// Writes target records which have counterparts as CSV
+pub fn write_target_matched<W: Write>(comp: &TableComparator, w: W) -> csv::Result<()> {
+    let points = comp.points();
+    let records = points.iter()
+        .flat_map(|p| comp.partition(p).target_matched);
+    write_csv(w, comp.target().format(), comp.target().header(), records)
+}
+
+/// Writes records with their raw fields in the dialect of the input
+///
+/// Records without raw fields are written with their values.
+pub fn write_csv<'a, W, I>(w: W, format: &CsvFormat, header: Option<&StringRecord>, records: I) -> csv::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item=&'a Record>,
+{
+    let mut wtr = format.writer_builder().from_writer(w);
+
+    if let Some(header) = header {
+        wtr.write_record(header)?;
+    }
+    for record in records {
+        match record.raw() {
+            Some(raw) => wtr.write_record(raw)?,
+            None => wtr.write_record(record.iter().map(|v| v.to_string()))?,
+        }
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Converts a record into an object whose keys are column names
+///
+/// An empty or repeated name is suffixed with the one-based position of the column like `memo_3`,
+/// so that no value is overwritten.
+pub fn record_to_json(columns: &Columns, record: &Record) -> serde_json::Value {
+    let mut obj = Map::new();
+    for (index, (col, value)) in columns.iter().zip(record.iter()).enumerate() {
+        let mut key = col.name().to_owned();
+        if key.is_empty() || obj.contains_key(&key) {
+            key = format!("{}_{}", key, index + 1);
+        }
+        obj.insert(key, value_to_json(value));
+    }
+    serde_json::Value::Object(obj)
+}
+
+/// Converts a value into JSON
+///
+/// Decimals are written as strings to keep their precision.
+pub fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::String(s) => json!(s),
+        Value::Decimal(d) => json!(d.to_string()),
+        Value::Date(d) => json!(d.to_string()),
+        Value::Integer(n) => json!(n),
+        Value::Bool(b) => json!(b),
+        Value::Time(_) | Value::DateTime(_) => json!(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDate;
+    use crate::core::compare_files;
+
+    #[test]
+    fn csv() {
+        let raw = StringRecord::from(vec!["2020/09/12", "1,000"]);
+        let columns: Columns = "date:Date, price:Decimal(group=',')".parse().unwrap();
+        let parser = crate::record::RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+        let parsed = parser.parse(&raw).unwrap().unwrap();
+        let derived: Record = vec![Value::String("a".to_owned()), Value::Null].into();
+        let header = StringRecord::from(vec!["Date", "Amount"]);
+
+        let mut buf = Vec::new();
+        write_csv(&mut buf, &CsvFormat::default(), Some(&header), vec![&parsed, &derived]).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "Date,Amount\n2020/09/12,\"1,000\"\na,\n");
+    }
+
+    const POSTING_COLUMNS: &str = "date:Date,description,account,unit,price:Decimal";
+    const LEDGER_COLUMNS: &str = "date:Date,description,price:Decimal";
+    const BANK_COLUMNS: &str = "date:Date,description,amount:Decimal";
+
+    #[test]
+    fn plain() {
+        let comp = compare_files(("examples/base.csv", POSTING_COLUMNS), ("examples/delta.csv", POSTING_COLUMNS),
+            "(date, price) = (date, price)");
+
+        let mut buf = Vec::new();
+        write_plain(&comp, &mut buf, false, false).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "\
+# 2020-09-12,-1000 (source has 1, target has 0: 1 extra on source)
+< 2020-09-12,Lunch,Liabilities:CreditCard,JPY,-1000
+# 2020-09-20,-4500 (source has 0, target has 1: 1 extra on target)
+> 2020-09-20,Adobe,Liabilities:CreditCard,JPY,-4500
+");
+    }
+
+    #[test]
+    fn summary() {
+        let comp = compare_files(("examples/base.csv", POSTING_COLUMNS), ("examples/delta.csv", POSTING_COLUMNS),
+            "(date, price) = (date, price)");
+
+        let mut buf = Vec::new();
+        write_summary(&comp.summary(), &mut buf).unwrap();
+        let out = String::from_utf8(buf).unwrap();
+        assert!(out.starts_with("## Summary\nrecords read: source 4, target 4\n"));
+        assert!(out.ends_with("\
+unmatched records: source 1, target 1
+unmatched total of source price: -1000
+unmatched total of target price: -4500
+"));
+    }
+
+    #[test]
+    fn changes() {
+        let comp = compare_files(("examples/base.csv", POSTING_COLUMNS), ("examples/revised.csv", POSTING_COLUMNS),
+            "(date, description, price) = (date, description, price)")
+            .with_key_split(crate::core::KeySplit::new(2, vec!["price".to_owned()]));
+
+        let mut buf = Vec::new();
+        write_plain(&comp, &mut buf, false, false).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "\
+# 2020-09-10,Diner (modified: price -5000 -> -5500)
+< 2020-09-10,Diner,Liabilities:CreditCard,JPY,-5000
+> 2020-09-10,Diner,Liabilities:CreditCard,JPY,-5500
+# 2020-09-12,Lunch (removed)
+< 2020-09-12,Lunch,Liabilities:CreditCard,JPY,-1000
+# 2020-09-20,Adobe (added)
+> 2020-09-20,Adobe,Liabilities:CreditCard,JPY,-4500
+");
+    }
+
+    #[test]
+    fn approximate_matches() {
+        let comp = compare_files(("examples/ledger_usd.csv", LEDGER_COLUMNS), ("examples/card_usd.csv", BANK_COLUMNS), "(date) = (date)");
+        let approx = crate::expr::parse_approx("price ~ amount within 0.01", comp.source().columns(), comp.target().columns()).unwrap();
+        let comp = comp.with_approx(vec![approx]);
+
+        let mut buf = Vec::new();
+        write_plain(&comp, &mut buf, false, false).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "\
+# 2020-09-10 (source has 1, target has 1: 1 extra on source, 1 extra on target)
+< 2020-09-10,Hotel,-120.00
+> 2020-09-10,HOTEL,-121.00
+~ 2020-09-05 (price -0.01)
+< 2020-09-05,Book,-12.99
+> 2020-09-05,BOOKSTORE,-13.00
+");
+    }
+
+    #[test]
+    fn group_sums() {
+        let comp = compare_files(("examples/postings.csv", POSTING_COLUMNS), ("examples/history.csv", LEDGER_COLUMNS), "(date) = (date)");
+        let sum_equal = crate::expr::parse_sum_equal("-price = price", comp.source().columns(), comp.target().columns()).unwrap();
+        let comp = comp.with_sum_equal(sum_equal);
+
+        let mut buf = Vec::new();
+        write_plain(&comp, &mut buf, false, false).unwrap();
+        assert!(String::from_utf8(buf).unwrap().starts_with("\
+# 2020-09-11 (sum of -price: source 200, target 100)
+< 2020-09-11,Vending Machine,Liabilities:CreditCard,JPY,-100
+< 2020-09-11,Vending Machine,Liabilities:CreditCard,JPY,-100
+> 2020-09-11,Mobile,100
+"));
+    }
+
+    #[test]
+    fn subset_matches() {
+        let comp = compare_files(("examples/split_ledger.csv", LEDGER_COLUMNS), ("examples/split_bank.csv", BANK_COLUMNS), "(date, price) = (date, amount)");
+        let sum_equal = crate::expr::parse_sum_equal("price = amount", comp.source().columns(), comp.target().columns()).unwrap();
+        let within = crate::expr::parse_approx("date ~ date within 0 days", comp.source().columns(), comp.target().columns()).unwrap();
+        let comp = comp.with_subset_search(crate::core::SubsetSearch::new(sum_equal).within(vec![within]));
+
+        assert_eq!(describe_subset(&comp.subset_matches()[0]), "2 source records for 1 target record");
+
+        let mut buf = Vec::new();
+        write_plain(&comp, &mut buf, true, false).unwrap();
+        let out = String::from_utf8(buf).unwrap();
+        let out = &out[out.find("+ (").unwrap()..];
+        assert!(out.contains(&format!("{}", "< 2020-09-25,Rent part 1,-50000".red())));
+        assert!(out.contains(&format!("{}", "> 2020-09-25,TRANSFER,-80000".green())));
+    }
+
+    #[test]
+    fn counts() {
+        let records: Vec<Record> = (0..5).map(|_| Record::from(vec![Value::Integer(1)])).collect();
+        let part = Partition {
+            source_matched: &records[..2],
+            target_matched: &records[2..4],
+            source_only: &records[4..],
+            target_only: &[],
+        };
+        assert_eq!(describe_counts(&part), "source has 3, target has 2: 1 extra on source");
+    }
+
+    #[test]
+    fn color_choice() {
+        assert_eq!("never".parse::<ColorChoice>().unwrap(), ColorChoice::Never);
+        assert!(ColorChoice::Always.enabled());
+        assert!(!ColorChoice::Never.enabled());
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+
+    #[test]
+    fn record() {
+        let columns: Columns = "date:Date, description, price:Decimal, fee:Decimal?".parse().unwrap();
+        let record: Record = vec![
+            Value::Date(NaiveDate::from_ymd(2020, 9, 12)),
+            Value::String("Lunch".to_owned()),
+            Value::Decimal((-1000).into()),
+            Value::Null,
+        ].into();
+        assert_eq!(serde_json::to_string(&record_to_json(&columns, &record)).unwrap(),
+            r#"{"date":"2020-09-12","description":"Lunch","price":"-1000","fee":null}"#);
+
+        let columns: Columns = "date:Date, memo, memo, ".parse().unwrap();
+        let record: Record = vec![
+            Value::Date(NaiveDate::from_ymd(2020, 9, 12)),
+            Value::String("Lunch".to_owned()),
+            Value::String("Card".to_owned()),
+            Value::Null,
+        ].into();
+        assert_eq!(serde_json::to_string(&record_to_json(&columns, &record)).unwrap(),
+            r#"{"date":"2020-09-12","memo":"Lunch","memo_3":"Card","_4":null}"#);
+    }
+}
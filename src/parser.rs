@@ -0,0 +1,852 @@
+use thiserror::Error;
+
+/// Byte range of a token or an expression in an input
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+#[derive(Error,Debug,Clone,PartialEq)]
+pub enum ErrorKind {
+    #[error("unexpected character `{0}`")]
+    UnexpectedChar(char),
+    #[error("a closing quote is missing")]
+    UnterminatedQuote,
+    #[error("expected {expected}, found {found}")]
+    Unexpected {
+        expected: String,
+        found: String,
+    },
+    #[error("undefined field name `{0}`")]
+    UndefinedField(String),
+    #[error("`{expr}` is {ty}, but {expected} is expected")]
+    TypeError {
+        expr: String,
+        ty: String,
+        expected: String,
+    },
+    #[error("`{0}` is Integer, but integer arithmetic is not supported")]
+    IntegerArithmetic(String),
+    #[error("a tuple cannot be {0}")]
+    NestedTuple(String),
+    #[error("the left side has {left} values, but the right side has {right}")]
+    LengthMismatch {
+        left: usize,
+        right: usize,
+    },
+    #[error("the value at position {position} is {left} on the left side, but {right} on the right side")]
+    PositionTypeMismatch {
+        position: usize,
+        left: String,
+        right: String,
+    },
+    #[error("invalid tolerance `{0}`")]
+    InvalidTolerance(String),
+    #[error("invalid number `{0}`")]
+    InvalidNumber(String),
+    #[error("unknown function `{0}`")]
+    UnknownFunction(String),
+    #[error("invalid regular expression: {0}")]
+    InvalidRegex(String),
+    #[error("`{name}` takes {expected} argument(s), but {found} given")]
+    WrongArguments {
+        name: String,
+        expected: String,
+        found: usize,
+    },
+}
+
+/// An error with the location in the input
+///
+/// It is displayed with the input and carets under the location.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ParseError {
+    kind: ErrorKind,
+    span: Span,
+    input: String,
+}
+
+impl ParseError {
+    pub fn new(kind: ErrorKind, span: Span, input: &str) -> Self {
+        ParseError {
+            kind,
+            span,
+            input: input.to_owned(),
+        }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the one-based column of the location in characters
+    pub fn column(&self) -> usize {
+        self.input[..self.span.start].chars().count() + 1
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let width = self.input[self.span.start..self.span.end].chars().count().max(1);
+        writeln!(f, "{} at column {}", self.kind, self.column())?;
+        writeln!(f, "  {}", self.input)?;
+        write!(f, "  {}{}", " ".repeat(self.column() - 1), "^".repeat(width))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Token {
+    /// A field name, or a quoted one like `` `Posting Date` ``
+    Ident(String),
+    Number(String),
+    /// A string literal like `"JPY"`
+    Str(String),
+    LParen,
+    RParen,
+    Comma,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Tilde,
+    End,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(name) => format!("`{}`", name),
+            Token::Number(n) => format!("`{}`", n),
+            Token::Str(s) => format!("{:?}", s),
+            Token::LParen => "`(`".to_owned(),
+            Token::RParen => "`)`".to_owned(),
+            Token::Comma => "`,`".to_owned(),
+            Token::Plus => "`+`".to_owned(),
+            Token::Minus => "`-`".to_owned(),
+            Token::Star => "`*`".to_owned(),
+            Token::Slash => "`/`".to_owned(),
+            Token::Equal => "`=`".to_owned(),
+            Token::NotEqual => "`!=`".to_owned(),
+            Token::Less => "`<`".to_owned(),
+            Token::LessEqual => "`<=`".to_owned(),
+            Token::Greater => "`>`".to_owned(),
+            Token::GreaterEqual => "`>=`".to_owned(),
+            Token::Tilde => "`~`".to_owned(),
+            Token::End => "the end".to_owned(),
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the longest of `names` at the beginning of `s` that would not be read as one identifier
+///
+/// Names made of digits are excluded since they are read as numbers.
+fn verbatim_name<'n>(s: &str, names: &[&'n str]) -> Option<&'n str> {
+    names.iter()
+        .copied()
+        .filter(|name| {
+            let mut chars = name.chars();
+            let is_ident = matches!(chars.next(), Some(c) if is_ident_start(c)) && chars.all(is_ident_continue);
+            let is_number = name.chars().all(|c| c.is_ascii_digit() || c == '.');
+            !is_ident && !is_number
+        })
+        .filter(|name| s.starts_with(name) && !s[name.len()..].starts_with(is_ident_continue))
+        .max_by_key(|name| name.len())
+}
+
+/// Splits an input into tokens followed by `Token::End`
+pub fn tokenize(input: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    tokenize_with_names(input, &[])
+}
+
+/// Splits an input into tokens reading `names` as identifiers verbatim
+///
+/// This lets field names like `Posting Date` or `unit-price` be written without backquotes.
+pub fn tokenize_with_names(input: &str, names: &[&str]) -> Result<Vec<(Token, Span)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if let Some(name) = verbatim_name(&input[start..], names) {
+            let end = start + name.len();
+            while matches!(chars.peek(), Some(&(i, _)) if i < end) {
+                chars.next();
+            }
+            tokens.push((Token::Ident(name.to_owned()), Span { start, end }));
+            continue;
+        }
+
+        let token = match c {
+            '(' => { chars.next(); Token::LParen },
+            ')' => { chars.next(); Token::RParen },
+            ',' => { chars.next(); Token::Comma },
+            '+' => { chars.next(); Token::Plus },
+            '-' => { chars.next(); Token::Minus },
+            '*' => { chars.next(); Token::Star },
+            '/' => { chars.next(); Token::Slash },
+            '=' => { chars.next(); Token::Equal },
+            '<' | '>' | '!' => {
+                chars.next();
+                match (c, chars.peek().map(|&(_, d)| d)) {
+                    ('<', Some('=')) => { chars.next(); Token::LessEqual },
+                    ('<', Some('>')) | ('!', Some('=')) => { chars.next(); Token::NotEqual },
+                    ('>', Some('=')) => { chars.next(); Token::GreaterEqual },
+                    ('<', _) => Token::Less,
+                    ('>', _) => Token::Greater,
+                    _ => {
+                        let span = Span { start, end: start + 1 };
+                        return Err(ParseError::new(ErrorKind::UnexpectedChar(c), span, input));
+                    },
+                }
+            },
+            '~' => { chars.next(); Token::Tilde },
+            '"' | '`' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, d)) if d == c => break,
+                        Some((_, '\\')) if c == '"' => match chars.peek() {
+                            Some(&(_, d)) if d == '"' || d == '\\' => {
+                                s.push(d);
+                                chars.next();
+                            },
+                            _ => s.push('\\'),
+                        },
+                        Some((_, d)) => s.push(d),
+                        None => return Err(ParseError::new(ErrorKind::UnterminatedQuote, Span { start, end: input.len() }, input)),
+                    }
+                }
+                if c == '"' { Token::Str(s) } else { Token::Ident(s) }
+            },
+            c if c.is_ascii_digit() => {
+                let mut s = String::new();
+                while let Some(&(_, d)) = chars.peek() {
+                    if d.is_ascii_digit() || d == '.' {
+                        s.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Number(s)
+            },
+            c if is_ident_start(c) => {
+                let mut s = String::new();
+                while let Some(&(_, d)) = chars.peek() {
+                    if is_ident_continue(d) {
+                        s.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Ident(s)
+            },
+            c => {
+                let span = Span { start, end: start + c.len_utf8() };
+                return Err(ParseError::new(ErrorKind::UnexpectedChar(c), span, input));
+            },
+        };
+        let end = chars.peek().map(|&(i, _)| i).unwrap_or_else(|| input.len());
+        tokens.push((token, Span { start, end }));
+    }
+
+    tokens.push((Token::End, Span { start: input.len(), end: input.len() }));
+    Ok(tokens)
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ExprKind {
+    Field(String),
+    Number(String),
+    /// Number of days like `2 days`
+    Days(String),
+    Str(String),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    /// Function call like `abs(price)`
+    Call(String, Vec<Expr>),
+    Compare(CmpOp, Box<Expr>, Box<Expr>),
+    /// `x between low and high`
+    Between(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `x in (a, b, c)`
+    In(Box<Expr>, Vec<Expr>),
+    /// `x matches "pattern"`
+    Matches(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    /// Parenthesized expressions other than a single one
+    Tuple(Vec<Expr>),
+}
+
+/// Comparison operator
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            CmpOp::Eq => "=",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+}
+
+/// A node of the syntax tree with its location
+#[derive(Debug,Clone,PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+/// Tolerance of an approximation like `0.01` or `3 days`
+#[derive(Debug,Clone,PartialEq)]
+pub struct ToleranceExpr {
+    pub value: String,
+    pub days: bool,
+    pub span: Span,
+}
+
+/// Recursive descent parser
+///
+/// ```text
+/// equation      := expr '=' expr
+/// approximation := expr '~' expr 'within' NUMBER ['day' | 'days']
+/// condition     := conjunction ('or' conjunction)*
+/// conjunction   := negation ('and' negation)*
+/// negation      := 'not' negation | comparison
+/// comparison    := expr [CMP expr | ['not'] ('between' expr 'and' expr | 'in' '(' [condition (',' condition)*] ')' | 'matches' STRING)]
+/// expr          := product (('+' | '-') product)*
+/// product       := unary (('*' | '/') unary)*
+/// unary         := '-' unary | primary
+/// primary       := IDENT ['(' [element (',' element)*] ')'] | NUMBER ['day' | 'days'] | STRING | '(' [element (',' element)*] ')'
+/// element       := condition in conditions, or expr otherwise
+/// ```
+#[derive(Debug)]
+pub struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+    /// Whether parentheses may contain conditions
+    in_condition: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Result<Self, ParseError> {
+        Self::with_names(input, &[])
+    }
+
+    /// Creates a parser reading `names` as field names verbatim
+    pub fn with_names(input: &'a str, names: &[&str]) -> Result<Self, ParseError> {
+        Ok(Parser {
+            input,
+            tokens: tokenize_with_names(input, names)?,
+            pos: 0,
+            in_condition: false,
+        })
+    }
+
+    fn peek(&self) -> &(Token, Span) {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> (Token, Span) {
+        let token = self.tokens[self.pos].clone();
+        if token.0 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let (token, span) = self.peek();
+        let kind = ErrorKind::Unexpected {
+            expected: expected.to_owned(),
+            found: token.describe(),
+        };
+        ParseError::new(kind, *span, self.input)
+    }
+
+    fn expect(&mut self, token: Token) -> Result<Span, ParseError> {
+        if self.peek().0 == token {
+            Ok(self.next().1)
+        } else {
+            Err(self.unexpected(&token.describe()))
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), ParseError> {
+        self.expect(Token::End).map(|_| ())
+    }
+
+    /// Parses `expr = expr` to the end
+    pub fn equation(mut self) -> Result<(Expr, Expr), ParseError> {
+        let lhs = self.expr()?;
+        self.expect(Token::Equal)?;
+        let rhs = self.expr()?;
+        self.expect_end()?;
+        Ok((lhs, rhs))
+    }
+
+    /// Parses `expr ~ expr within tolerance` to the end
+    pub fn approximation(mut self) -> Result<(Expr, Expr, ToleranceExpr), ParseError> {
+        let lhs = self.expr()?;
+        self.expect(Token::Tilde)?;
+        let rhs = self.expr()?;
+        match self.peek().0 {
+            Token::Ident(ref word) if word == "within" => { self.next(); },
+            _ => return Err(self.unexpected("`within`")),
+        }
+        let (value, span) = match self.peek().clone() {
+            (Token::Number(n), span) => {
+                self.next();
+                (n, span)
+            },
+            _ => return Err(self.unexpected("a tolerance")),
+        };
+        let (days, span) = match self.peek().clone() {
+            (Token::Ident(ref unit), unit_span) if unit == "day" || unit == "days" => {
+                self.next();
+                (true, span.to(unit_span))
+            },
+            _ => (false, span),
+        };
+        self.expect_end()?;
+        Ok((lhs, rhs, ToleranceExpr { value, days, span }))
+    }
+
+    /// Parses a boolean condition to the end
+    pub fn condition_to_end(mut self) -> Result<Expr, ParseError> {
+        self.in_condition = true;
+        let condition = self.condition()?;
+        self.expect_end()?;
+        Ok(condition)
+    }
+
+    /// Consumes a keyword like `and` if it comes next
+    fn keyword(&mut self, word: &str) -> Option<Span> {
+        match self.peek() {
+            (Token::Ident(ref w), span) if w == word => {
+                let span = *span;
+                self.next();
+                Some(span)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn condition(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.conjunction()?;
+        while self.keyword("or").is_some() {
+            let rhs = self.conjunction()?;
+            lhs = binary(ExprKind::Or, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn conjunction(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.negation()?;
+        while self.keyword("and").is_some() {
+            let rhs = self.negation()?;
+            lhs = binary(ExprKind::And, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn negation(&mut self) -> Result<Expr, ParseError> {
+        // `not` followed by an operator like `not = 1` is a field name
+        let is_keyword = matches!(self.tokens.get(self.pos + 1),
+            Some((Token::Ident(_), _)) | Some((Token::Number(_), _)) | Some((Token::Str(_), _)) | Some((Token::LParen, _)) | Some((Token::Minus, _)));
+        if !is_keyword {
+            return self.comparison();
+        }
+        match self.keyword("not") {
+            Some(start) => {
+                let operand = self.negation()?;
+                let span = start.to(operand.span);
+                Ok(Expr {
+                    kind: ExprKind::Not(Box::new(operand)),
+                    span,
+                })
+            },
+            None => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let lhs = self.expr()?;
+        let negated = self.keyword("not");
+        let op = match self.peek().0 {
+            Token::Equal => Some(CmpOp::Eq),
+            Token::NotEqual => Some(CmpOp::Ne),
+            Token::Less => Some(CmpOp::Lt),
+            Token::LessEqual => Some(CmpOp::Le),
+            Token::Greater => Some(CmpOp::Gt),
+            Token::GreaterEqual => Some(CmpOp::Ge),
+            _ => None,
+        };
+
+        let expr = match op {
+            Some(op) if negated.is_none() => {
+                self.next();
+                let rhs = self.expr()?;
+                binary(|lhs, rhs| ExprKind::Compare(op, lhs, rhs), lhs, rhs)
+            },
+            _ if self.keyword("between").is_some() => {
+                let low = self.expr()?;
+                if self.keyword("and").is_none() {
+                    return Err(self.unexpected("`and`"));
+                }
+                let high = self.expr()?;
+                let span = lhs.span.to(high.span);
+                Expr {
+                    kind: ExprKind::Between(Box::new(lhs), Box::new(low), Box::new(high)),
+                    span,
+                }
+            },
+            _ if matches!(self.peek().0, Token::Ident(ref w) if w == "in") => {
+                self.next();
+                let (items, end) = self.arguments()?;
+                let span = lhs.span.to(end);
+                Expr {
+                    kind: ExprKind::In(Box::new(lhs), items),
+                    span,
+                }
+            },
+            _ if self.keyword("matches").is_some() => {
+                let pattern = self.primary()?;
+                binary(ExprKind::Matches, lhs, pattern)
+            },
+            _ if negated.is_some() => return Err(self.unexpected("`between`, `in` or `matches`")),
+            _ => return Ok(lhs),
+        };
+
+        match negated {
+            Some(_) => Ok(Expr {
+                span: expr.span,
+                kind: ExprKind::Not(Box::new(expr)),
+            }),
+            None => Ok(expr),
+        }
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = match self.peek().0 {
+                Token::Plus => ExprKind::Add,
+                Token::Minus => ExprKind::Sub,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.product()?;
+            let span = lhs.span.to(rhs.span);
+            lhs = Expr {
+                kind: op(Box::new(lhs), Box::new(rhs)),
+                span,
+            };
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek().0 {
+                Token::Star => ExprKind::Mul,
+                Token::Slash => ExprKind::Div,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.unary()?;
+            let span = lhs.span.to(rhs.span);
+            lhs = Expr {
+                kind: op(Box::new(lhs), Box::new(rhs)),
+                span,
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.peek().0 == Token::Minus {
+            let span = self.next().1;
+            let operand = self.unary()?;
+            let span = span.to(operand.span);
+            Ok(Expr {
+                kind: ExprKind::Neg(Box::new(operand)),
+                span,
+            })
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let kind = match self.peek().0 {
+            Token::Ident(ref name) if self.tokens[self.pos + 1].0 == Token::LParen => {
+                let name = name.clone();
+                let start = self.next().1;
+                let (args, end) = self.arguments()?;
+                return Ok(Expr {
+                    kind: ExprKind::Call(name, args),
+                    span: start.to(end),
+                });
+            },
+            Token::Ident(ref name) => ExprKind::Field(name.clone()),
+            Token::Number(ref n) => match self.tokens[self.pos + 1].0 {
+                Token::Ident(ref unit) if unit == "day" || unit == "days" => {
+                    let n = n.clone();
+                    let start = self.next().1;
+                    let end = self.next().1;
+                    return Ok(Expr {
+                        kind: ExprKind::Days(n),
+                        span: start.to(end),
+                    });
+                },
+                _ => ExprKind::Number(n.clone()),
+            },
+            Token::Str(ref s) => ExprKind::Str(s.clone()),
+            Token::LParen => return self.parenthesized(),
+            _ => return Err(self.unexpected("a field name or a value")),
+        };
+        let span = self.next().1;
+        Ok(Expr { kind, span })
+    }
+
+    fn parenthesized(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek().1;
+        let (mut exprs, end) = self.arguments()?;
+        if exprs.len() == 1 {
+            let expr = exprs.pop().unwrap();
+            Ok(Expr {
+                span: start.to(end),
+                ..expr
+            })
+        } else {
+            Ok(Expr {
+                kind: ExprKind::Tuple(exprs),
+                span: start.to(end),
+            })
+        }
+    }
+
+    fn element(&mut self) -> Result<Expr, ParseError> {
+        if self.in_condition {
+            self.condition()
+        } else {
+            self.expr()
+        }
+    }
+
+    /// Parses comma-separated expressions in parentheses and returns them with the span of `)`
+    fn arguments(&mut self) -> Result<(Vec<Expr>, Span), ParseError> {
+        self.expect(Token::LParen)?;
+        let mut exprs = Vec::new();
+        if self.peek().0 != Token::RParen {
+            exprs.push(self.element()?);
+            while self.peek().0 == Token::Comma {
+                self.next();
+                exprs.push(self.element()?);
+            }
+        }
+        let end = match self.peek().0 {
+            Token::RParen => self.next().1,
+            _ if exprs.is_empty() => return Err(self.unexpected("a field name, a value or `)`")),
+            _ => return Err(self.unexpected("`,` or `)`")),
+        };
+        Ok((exprs, end))
+    }
+}
+
+fn binary(kind: impl FnOnce(Box<Expr>, Box<Expr>) -> ExprKind, lhs: Expr, rhs: Expr) -> Expr {
+    let span = lhs.span.to(rhs.span);
+    Expr {
+        kind: kind(Box::new(lhs), Box::new(rhs)),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<Token> = tokenize(r#"(`Posting Date`, -金額) = ("a,b", 1.5)"#).unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+        assert_eq!(tokens, vec![
+            Token::LParen, Token::Ident("Posting Date".to_owned()), Token::Comma,
+            Token::Minus, Token::Ident("金額".to_owned()), Token::RParen, Token::Equal,
+            Token::LParen, Token::Str("a,b".to_owned()), Token::Comma, Token::Number("1.5".to_owned()),
+            Token::RParen, Token::End,
+        ]);
+
+        let (token, _) = tokenize(r#""a\"b\\c\d+""#).unwrap().remove(0);
+        assert_eq!(token, Token::Str(r#"a"b\c\d+"#.to_owned()));
+    }
+
+    #[test]
+    fn verbatim_names() {
+        let names = ["Posting Date", "unit-price", "unit", "1st payment", "2020", "and"];
+        let expr = |s: &str| shape(&Parser::with_names(s, &names).unwrap().expr().unwrap());
+        assert_eq!(expr("(Posting Date, unit-price * 2, 1st payment)"), "[Posting Date (* unit-price 2) 1st payment]");
+        assert_eq!(expr("unit - price"), "(- unit price)");
+        assert_eq!(expr("unit-prices"), "(- unit prices)");
+        assert_eq!(expr("2020 + and"), "(+ 2020 and)");
+
+        let condition = |s: &str| shape(&Parser::with_names(s, &names).unwrap().condition_to_end().unwrap());
+        assert_eq!(condition("not = 1 and not not"), "(and (= not 1) (not not))");
+        assert_eq!(condition("or > 1 or and between 1 and 2"), "(or (> or 1) (between and 1 2))");
+    }
+
+    #[test]
+    fn precedence() {
+        let (lhs, _) = Parser::new("a - -b + c = x").unwrap().equation().unwrap();
+        let field = |name: &str, start| Expr {
+            kind: ExprKind::Field(name.to_owned()),
+            span: Span { start, end: start + 1 },
+        };
+        assert_eq!(lhs, Expr {
+            kind: ExprKind::Add(
+                Box::new(Expr {
+                    kind: ExprKind::Sub(
+                        Box::new(field("a", 0)),
+                        Box::new(Expr {
+                            kind: ExprKind::Neg(Box::new(field("b", 5))),
+                            span: Span { start: 4, end: 6 },
+                        })),
+                    span: Span { start: 0, end: 6 },
+                }),
+                Box::new(field("c", 9))),
+            span: Span { start: 0, end: 10 },
+        });
+    }
+
+    fn shape(expr: &Expr) -> String {
+        match expr.kind {
+            ExprKind::Field(ref name) => name.clone(),
+            ExprKind::Number(ref n) => n.clone(),
+            ExprKind::Days(ref n) => format!("{}d", n),
+            ExprKind::Str(ref s) => format!("{:?}", s),
+            ExprKind::Neg(ref e) => format!("(- {})", shape(e)),
+            ExprKind::Add(ref a, ref b) => format!("(+ {} {})", shape(a), shape(b)),
+            ExprKind::Sub(ref a, ref b) => format!("(- {} {})", shape(a), shape(b)),
+            ExprKind::Mul(ref a, ref b) => format!("(* {} {})", shape(a), shape(b)),
+            ExprKind::Div(ref a, ref b) => format!("(/ {} {})", shape(a), shape(b)),
+            ExprKind::Call(ref name, ref args) => format!("({} {})", name, args.iter().map(shape).collect::<Vec<_>>().join(" ")),
+            ExprKind::Tuple(ref exprs) => format!("[{}]", exprs.iter().map(shape).collect::<Vec<_>>().join(" ")),
+            ExprKind::Compare(op, ref a, ref b) => format!("({} {} {})", op.symbol(), shape(a), shape(b)),
+            ExprKind::Between(ref x, ref a, ref b) => format!("(between {} {} {})", shape(x), shape(a), shape(b)),
+            ExprKind::In(ref x, ref items) => format!("(in {} {})", shape(x), items.iter().map(shape).collect::<Vec<_>>().join(" ")),
+            ExprKind::Matches(ref x, ref p) => format!("(matches {} {})", shape(x), shape(p)),
+            ExprKind::Not(ref e) => format!("(not {})", shape(e)),
+            ExprKind::And(ref a, ref b) => format!("(and {} {})", shape(a), shape(b)),
+            ExprKind::Or(ref a, ref b) => format!("(or {} {})", shape(a), shape(b)),
+        }
+    }
+
+    #[test]
+    fn products() {
+        let mut parser = Parser::new("a + b * -c / 100 - abs(d - e) * (f + g)").unwrap();
+        let expr = parser.expr().unwrap();
+        assert_eq!(shape(&expr), "(- (+ a (/ (* b (- c)) 100)) (* (abs (- d e)) (+ f g)))");
+        assert_eq!(expr.span, Span { start: 0, end: 39 });
+
+        let (lhs, rhs) = Parser::new("(x, round(y, 2)) = (price * 1.1, z)").unwrap().equation().unwrap();
+        assert_eq!(shape(&lhs), "[x (round y 2)]");
+        assert_eq!(shape(&rhs), "[(* price 1.1) z]");
+
+        let (lhs, _) = Parser::new("(date - 1 day, 3 + date) = x").unwrap().equation().unwrap();
+        assert_eq!(shape(&lhs), "[(- date 1d) (+ 3 date)]");
+    }
+
+    #[test]
+    fn conditions() {
+        let condition = |s: &str| shape(&Parser::new(s).unwrap().condition_to_end().unwrap());
+        assert_eq!(condition("a != 0 and not b matches \"^x\" or c <= 1"),
+            "(or (and (!= a 0) (not (matches b \"^x\"))) (<= c 1))");
+        assert_eq!(condition("a between 1 and 2 and b not in (\"x\", \"y\")"),
+            "(and (between a 1 2) (not (in b \"x\" \"y\")))");
+        assert_eq!(condition("not (a > 1 or b <> 2) and (c + 1) * 2 >= d"),
+            "(and (not (or (> a 1) (!= b 2))) (>= (* (+ c 1) 2) d))");
+        assert_eq!(condition("flag"), "flag");
+
+        let err = Parser::new("a between 1 or 2").unwrap().condition_to_end().unwrap_err();
+        assert_eq!(err.column(), 13);
+        assert!(Parser::new("a not = 1").unwrap().condition_to_end().is_err());
+        assert!(Parser::new("a ! 1").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let err = Parser::new("(date, price) (date, price)").unwrap().equation().unwrap_err();
+        assert_eq!(err.column(), 15);
+        assert_eq!(err.to_string(), "\
+expected `=`, found `(` at column 15
+  (date, price) (date, price)
+                ^");
+
+        let err = Parser::new("(date, price = (date, price)").unwrap().equation().unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::Unexpected {
+            expected: "`,` or `)`".to_owned(),
+            found: "`=`".to_owned(),
+        });
+
+        let err = Parser::new("\"JPY = unit").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnterminatedQuote);
+
+        let err = Parser::new("price ~ price within 0.01 yen").unwrap().approximation().unwrap_err();
+        assert_eq!(err.column(), 27);
+    }
+}
@@ -29,21 +29,25 @@ where
         }
     }
 
-    pub fn representatives<'a>(&'a self) -> impl Iterator<Item=&'a R> {
+    pub fn representatives(&self) -> impl Iterator<Item=&R> {
         self.classes.keys()
     }
 
-    pub fn classes<'a>(&'a self) -> impl Iterator<Item=&'a Vec<T>> {
+    pub fn classes(&self) -> impl Iterator<Item=&Vec<T>> {
         self.classes.values()
     }
 
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(&'a R, &'a Vec<T>)> {
+    pub fn iter(&self) -> impl Iterator<Item=(&R, &Vec<T>)> {
         self.classes.iter()
     }
 
     pub fn len(&self) -> usize {
         self.classes.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
 }
 
 impl<R, T, P> Quotient<R, T, P>
@@ -51,26 +55,26 @@ where
     P: Projection<Domain=T, Target=R>,
     R: Eq + Hash,
 {
-    pub fn get<Q: ?Sized>(&self, point: &Q) -> Option<&Vec<T>>
+    pub fn get<Q>(&self, point: &Q) -> Option<&Vec<T>>
         where
             R: Borrow<Q>,
-            Q: Hash + Eq,
+            Q: Hash + Eq + ?Sized,
     {
         self.classes.get(point)
     }
 
-    pub fn contains_representative<Q: ?Sized>(&self, point: &Q) -> bool
+    pub fn contains_representative<Q>(&self, point: &Q) -> bool
         where
             R: Borrow<Q>,
-            Q: Hash + Eq,
+            Q: Hash + Eq + ?Sized,
     {
         self.classes.contains_key(point)
     }
 
-    pub fn get_mut<Q: ?Sized>(&mut self, point: &Q) -> Option<&mut Vec<T>>
+    pub fn get_mut<Q>(&mut self, point: &Q) -> Option<&mut Vec<T>>
         where
             R: Borrow<Q>,
-            Q: Hash + Eq,
+            Q: Hash + Eq + ?Sized,
     {
         self.classes.get_mut(point)
     }
@@ -92,10 +96,11 @@ where
     /// This function only sees the numbers of the elements of equivalent classes.
     /// If the number of an equivalent class of `self` is greater than that of `other`,
     /// it treats that they are different.
+    /// The iterator yields such representatives with the excess of the numbers.
     pub fn difference<'a, Q>(&'a self, other: &'a Quotient<R, T, Q>) -> Difference<'a, R, T, Q> {
         Difference {
             iter: self.classes.iter(),
-            other: other,
+            other,
         }
     }
 }
@@ -110,19 +115,19 @@ where
     R: Eq + Hash,
     Q: Projection<Domain=T, Target=R>,
 {
-    type Item = &'a R;
+    type Item = (&'a R, usize);
 
-    fn next(&mut self) -> Option<&'a R> {
+    fn next(&mut self) -> Option<(&'a R, usize)> {
         loop {
             let (repr, class) = self.iter.next()?;
             match self.other.get(repr) {
                 Some(other_class) => {
                     if class.len() > other_class.len() {
-                        return Some(repr);
+                        return Some((repr, class.len() - other_class.len()));
                     }
                 },
                 None => {
-                    return Some(repr);
+                    return Some((repr, class.len()));
                 }
             }
         }
@@ -162,8 +167,8 @@ mod test {
         assert_eq!(quot.get(&2), Some(&vec![12]));
         assert_eq!(quot.get(&100), None);
 
-        assert_eq!(quot.contains_representative(&0), true);
-        assert_eq!(quot.contains_representative(&1000), false);
+        assert!(quot.contains_representative(&0));
+        assert!(!quot.contains_representative(&1000));
     }
 
     #[test]
@@ -182,9 +187,11 @@ mod test {
         b.push(1);
         b.push(12);
 
-        let mut diffs: Vec<usize> = a.difference(&b).cloned().collect();
+        let mut diffs: Vec<(usize, usize)> = a.difference(&b)
+            .map(|(repr, delta)| (*repr, delta))
+            .collect();
         diffs.sort();
 
-        assert_eq!(diffs, vec![0, 1]);
+        assert_eq!(diffs, vec![(0, 1), (1, 1)]);
     }
 }
@@ -1,19 +1,62 @@
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 use csv::StringRecord;
-use chrono::NaiveDate;
 use thiserror::Error;
 use log::warn;
 use crate::{Tag, Value};
 use crate::expr::Term;
-use crate::columns::Columns;
+use crate::columns::{Columns, Field};
+use crate::format::TemporalError;
 
-#[derive(Debug,Clone,PartialEq,Eq,Hash,Ord,PartialOrd)]
-pub struct Record(Vec<Value>);
+/// Typed record
+///
+/// A record read from a CSV file keeps its raw fields.
+/// Equality, ordering and hashing only see the typed values.
+#[derive(Debug,Clone)]
+pub struct Record {
+    values: Vec<Value>,
+    raw: Option<StringRecord>,
+}
+
+impl Record {
+    /// Returns the fields of the CSV record from which this record is parsed
+    pub fn raw(&self) -> Option<&StringRecord> {
+        self.raw.as_ref()
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl Eq for Record {}
+
+impl Hash for Record {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.values.hash(state)
+    }
+}
+
+impl PartialOrd for Record {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Record {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.values.cmp(&other.values)
+    }
+}
 
 impl From<Vec<Value>> for Record {
-    fn from(vec: Vec<Value>) -> Record {
-        Record(vec)
+    fn from(values: Vec<Value>) -> Record {
+        Record {
+            values,
+            raw: None,
+        }
     }
 }
 
@@ -21,19 +64,19 @@ impl Deref for Record {
     type Target = Vec<Value>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.values
     }
 }
 
 impl DerefMut for Record {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.values
     }
 }
 
 impl std::fmt::Display for Record {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let mut iter = self.0.iter();
+        let mut iter = self.values.iter();
         if let Some(first) = iter.next() {
             write!(f, "{}", first)?;
             for field in iter {
@@ -47,14 +90,31 @@ impl std::fmt::Display for Record {
 #[derive(Error,Debug)]
 pub enum ParseError {
     #[error("Field value is not a decimal")]
-    Decimal(#[from] rust_decimal::Error),
+    Decimal(#[from] crate::format::DecimalError),
     #[error("Field value is not a date")]
-    Date,
+    Date(#[source] TemporalError),
+    #[error("Field value is not a time")]
+    Time(#[source] TemporalError),
+    #[error("Field value is not a date and time")]
+    DateTime(#[source] TemporalError),
+    #[error("Field value is not an integer")]
+    Integer(#[from] std::num::ParseIntError),
+    #[error("Field value is not a boolean")]
+    Bool,
     #[error("Lengths of specified columns and a record does not match: columns: {columns}, record: {record}")]
     DifferentLength {
         columns: usize,
         record: usize,
     },
+    #[error("Record has {record} fields but field {field} is required")]
+    MissingField {
+        field: usize,
+        record: usize,
+    },
+    #[error("Column `{0}` is not found in the header")]
+    UndefinedHeader(String),
+    #[error("Column `{0}` refers to the header but no header is given")]
+    NoHeader(String),
 }
 
 #[derive(Debug)]
@@ -70,10 +130,34 @@ impl RecordParserBuilder<()> {
     }
 }
 
+impl Default for RecordParserBuilder<()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RecordParserBuilder<bool> {
     pub fn from_columns<'a>(self, columns: &'a Columns) -> RecordParser<'a> {
+        let positions = columns.iter()
+            .enumerate()
+            .map(|(index, col)| {
+                match col.field() {
+                    None => Some(index),
+                    Some(Field::Position(pos)) => Some(*pos),
+                    Some(Field::Header(_)) => None,
+                }
+            })
+            .collect();
+        let width = if columns.is_selective() {
+            None
+        } else {
+            Some(columns.len())
+        };
+
         RecordParser {
-            columns: columns,
+            columns,
+            positions,
+            width,
             ignore_length_mismatch: self.ignore_length_mismatch,
         }
     }
@@ -87,65 +171,184 @@ impl<LenMis> RecordParserBuilder<LenMis> {
     }
 }
 
+/// Parser converting CSV records into typed records
+///
+/// A column is read from the field given by its explicit location if any.
+/// Otherwise the `i`-th column is read from the `i`-th field of a CSV record,
+/// and all fields of a record must be declared.
+/// Call `with_header` to read columns from the fields having the same names in a header.
 #[derive(Debug,Clone)]
 pub struct RecordParser<'a> {
     columns: &'a Columns,
+    positions: Vec<Option<usize>>,
+    width: Option<usize>,
     ignore_length_mismatch: bool,
 }
 
 impl<'a> RecordParser<'a> {
+    /// Returns a parser which reads each column from the header field of the same name
+    ///
+    /// Columns located by position keep their positions.
+    pub fn with_header(&self, header: &StringRecord) -> Result<RecordParser<'a>, ParseError> {
+        let positions = self.columns.iter()
+            .map(|col| {
+                let name = match col.field() {
+                    Some(Field::Position(pos)) => return Ok(Some(*pos)),
+                    Some(Field::Header(name)) => name.as_str(),
+                    None => col.name(),
+                };
+                header.iter()
+                    .position(|field| field.trim() == name)
+                    .map(Some)
+                    .ok_or_else(|| ParseError::UndefinedHeader(name.to_owned()))
+            })
+            .collect::<Result<Vec<Option<usize>>, ParseError>>()?;
+        let width = if self.columns.is_selective() {
+            None
+        } else {
+            Some(header.len())
+        };
+
+        Ok(RecordParser {
+            columns: self.columns,
+            positions,
+            width,
+            ignore_length_mismatch: self.ignore_length_mismatch,
+        })
+    }
+
+    /// Checks that every column is located without a header
+    pub fn without_header(&self) -> Result<(), ParseError> {
+        match self.columns.iter().find_map(|col| match col.field() {
+            Some(Field::Header(name)) => Some(name),
+            _ => None,
+        }) {
+            Some(name) => Err(ParseError::NoHeader(name.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    fn length_mismatch(&self, record: &StringRecord, err: ParseError) -> Result<Option<Record>, ParseError> {
+        if self.ignore_length_mismatch {
+            warn!("Length mismatch: Ignored a record: {:?}", record);
+            Ok(None)
+        } else {
+            Err(err)
+        }
+    }
+
     pub fn parse(&self, record: &StringRecord) -> Result<Option<Record>, ParseError> {
-        if self.columns.len() != record.len() {
-            if self.ignore_length_mismatch {
-                warn!("Length mismatch: Ignored a record: {:?}", record);
-                return Ok(None);
-            } else {
-                return Err(ParseError::DifferentLength {
-                    columns: self.columns.len(),
+        if let Some(width) = self.width {
+            if width != record.len() {
+                return self.length_mismatch(record, ParseError::DifferentLength {
+                    columns: width,
                     record: record.len(),
                 });
             }
         }
 
-        let fields = record.iter()
-            .zip(self.columns.iter().map(|(_, tag)| *tag))
-            .map(|(field, tag)| {
-                match tag {
-                    Tag::String => Ok(Value::String(field.to_owned())),
-                    Tag::Decimal => field.parse()
-                        .map(|d| Value::Decimal(d))
-                        .map_err(|e| e.into()),
-                    Tag::Date => {
-                        if let Ok(date) = NaiveDate::parse_from_str(field, "%Y-%m-%d") {
-                            Ok(Value::Date(date))
-                        } else if let Ok(date) = NaiveDate::parse_from_str(field, "%Y/%m/%d") {
-                            Ok(Value::Date(date))
-                        } else if let Ok(date) = NaiveDate::parse_from_str(field, "%Y年%m月%d日") {
-                            Ok(Value::Date(date))
-                        } else {
-                            Err(ParseError::Date)
-                        }
-                    },
-                }
-            })
-            .collect::<Result<Vec<Value>, ParseError>>();
+        let mut fields = Vec::with_capacity(self.columns.len());
+        for (pos, col) in self.positions.iter().zip(self.columns.iter()) {
+            let pos = pos.ok_or_else(|| ParseError::NoHeader(col.name().to_owned()))?;
+            let field = match record.get(pos) {
+                Some(field) => field,
+                None => return self.length_mismatch(record, ParseError::MissingField {
+                    field: pos + 1,
+                    record: record.len(),
+                }),
+            };
+            if col.is_nullable() && field.trim().is_empty() {
+                fields.push(Value::Null);
+            } else {
+                fields.push(parse_field(field, col.tag())?);
+            }
+        }
+
+        Ok(Some(Record {
+            values: fields,
+            raw: Some(record.clone()),
+        }))
+    }
+}
+
+fn parse_field(field: &str, tag: &Tag) -> Result<Value, ParseError> {
+    match tag {
+        Tag::String => Ok(Value::String(field.to_owned())),
+        Tag::Decimal(format) => format.parse(field)
+            .map(Value::Decimal)
+            .map_err(|e| e.into()),
+        Tag::Date(format) => format.parse(field)
+            .map(Value::Date)
+            .map_err(ParseError::Date),
+        Tag::Integer => field.trim().parse()
+            .map(Value::Integer)
+            .map_err(|e| e.into()),
+        Tag::Bool => parse_bool(field)
+            .map(Value::Bool)
+            .ok_or(ParseError::Bool),
+        Tag::Time(format) => format.parse(field)
+            .map(Value::Time)
+            .map_err(ParseError::Time),
+        Tag::DateTime(format) => format.parse(field)
+            .map(Value::DateTime)
+            .map_err(ParseError::DateTime),
+    }
+}
 
-        fields.map(|f| Some(Record(f)))
+fn parse_bool(field: &str) -> Option<bool> {
+    match field.trim().to_lowercase().as_str() {
+        "true" | "t" | "yes" | "y" | "1" => Some(true),
+        "false" | "f" | "no" | "n" | "0" => Some(false),
+        _ => None,
     }
 }
 
 #[derive(Debug,Clone,PartialEq,Eq,Hash)]
-pub struct Mapping(Vec<Term>);
+pub struct Mapping {
+    terms: Vec<Term>,
+    zero_if_null: Vec<usize>,
+}
 
 impl From<Vec<Term>> for Mapping {
     fn from(terms: Vec<Term>) -> Self {
-        Self(terms)
+        Self {
+            terms,
+            zero_if_null: Vec::new(),
+        }
     }
 }
 
 impl Mapping {
+    /// Makes nulls in decimal columns zero before evaluating terms
+    pub fn null_as_zero(self, columns: &Columns) -> Self {
+        let zero_if_null = columns.iter()
+            .enumerate()
+            .filter(|(_, col)| matches!(col.tag(), Tag::Decimal(_)))
+            .map(|(index, _)| index)
+            .collect();
+
+        Mapping {
+            zero_if_null,
+            ..self
+        }
+    }
+
     pub fn apply(&self, fields: &[Value]) -> Vec<Value> {
-        self.0.iter()
+        if self.zero_if_null.iter().any(|&index| fields[index] == Value::Null) {
+            let mut fields = fields.to_vec();
+            for &index in self.zero_if_null.iter() {
+                if fields[index] == Value::Null {
+                    fields[index] = Value::Decimal(0.into());
+                }
+            }
+            self.eval(&fields)
+        } else {
+            self.eval(fields)
+        }
+    }
+
+    fn eval(&self, fields: &[Value]) -> Vec<Value> {
+        self.terms.iter()
             .map(|term| term.eval(fields))
             .collect()
     }
@@ -156,21 +359,156 @@ impl crate::quotient::Projection for Mapping {
     type Target = Record;
 
     fn project(&self, x: &Self::Domain) -> Self::Target {
-        Record(self.apply(&x.0))
+        Record::from(self.apply(x))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use chrono::NaiveDate;
     use crate::Value;
     use crate::expr::Term;
 
     #[test]
     fn mapping_apply() {
-        let mapping: Mapping = vec![Term::Val(1), Term::Neg(0)].into();
-        let record = vec![Value::Decimal(10), Value::String("hello".to_owned())];
+        let mapping: Mapping = vec![Term::Val(1), Term::Negate(Box::new(Term::Val(0)))].into();
+        let record = vec![Value::Decimal(10.into()), Value::String("hello".to_owned())];
         assert_eq!(mapping.apply(&record),
-            vec![Value::String("hello".to_owned()), Value::Decimal(-10)]);
+            vec![Value::String("hello".to_owned()), Value::Decimal((-10).into())]);
+    }
+
+    #[test]
+    fn mapping_null() {
+        let columns: Columns = "memo:String?, price:Decimal?, fee:Decimal?".parse().unwrap();
+        let terms = vec![Term::Val(0), Term::Negate(Box::new(Term::Val(1))), Term::Sum(vec![Term::Val(1), Term::Val(2)])];
+        let record = vec![Value::Null, Value::Decimal(10.into()), Value::Null];
+
+        let mapping: Mapping = terms.clone().into();
+        assert_eq!(mapping.apply(&record),
+            vec![Value::Null, Value::Decimal((-10).into()), Value::Null]);
+
+        let mapping = Mapping::from(terms).null_as_zero(&columns);
+        assert_eq!(mapping.apply(&record),
+            vec![Value::Null, Value::Decimal((-10).into()), Value::Decimal(10.into())]);
+    }
+
+    #[test]
+    fn parse_nullable() {
+        let columns: Columns = "memo:String?, price:Decimal?, date:Date".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+
+        let record = StringRecord::from(vec!["", " ", "2020/09/12"]);
+        assert_eq!(parser.parse(&record).unwrap(),
+            Some(Record::from(vec![
+                Value::Null,
+                Value::Null,
+                Value::Date(NaiveDate::from_ymd(2020, 9, 12)),
+            ])));
+
+        let record = StringRecord::from(vec!["", "", ""]);
+        assert!(parser.parse(&record).is_err());
+    }
+
+    #[test]
+    fn parse_with_header() {
+        let columns: Columns = "date:Date, price:Decimal".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+        let header = StringRecord::from(vec!["price", "description", "date"]);
+        let parser = parser.with_header(&header).unwrap();
+
+        let record = StringRecord::from(vec!["-1000", "Lunch", "2020/09/12"]);
+        assert_eq!(parser.parse(&record).unwrap(),
+            Some(Record::from(vec![
+                Value::Date(NaiveDate::from_ymd(2020, 9, 12)),
+                Value::Decimal((-1000).into()),
+            ])));
+
+        let record = StringRecord::from(vec!["-1000", "2020/09/12"]);
+        assert!(parser.parse(&record).is_err());
+
+        let header = StringRecord::from(vec!["price", "description"]);
+        assert!(RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns)
+            .with_header(&header)
+            .is_err());
+    }
+
+    #[test]
+    fn parse_date_format() {
+        let columns: Columns = "us:Date(%m/%d/%Y), eu:Date(%d.%m.%Y|%Y%m%d), any:Date(%m/%d/%Y|%d/%m/%Y)".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+
+        let record = StringRecord::from(vec!["09/12/2020", "20200912", "12/12/2020"]);
+        let date = Value::Date(NaiveDate::from_ymd(2020, 9, 12));
+        let record = parser.parse(&record).unwrap().unwrap();
+        assert_eq!(record[0], date);
+        assert_eq!(record[1], date);
+        assert_eq!(record[2], Value::Date(NaiveDate::from_ymd(2020, 12, 12)));
+
+        let record = StringRecord::from(vec!["09/12/2020", "12.09.2020", "09/12/2020"]);
+        match parser.parse(&record) {
+            Err(ParseError::Date(TemporalError::Ambiguous)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_types() {
+        let columns: Columns = "count:Integer, pending:Bool, time:Time, at:DateTime".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+
+        let record = StringRecord::from(vec![" 12", "Yes", "10:30", "2020-09-12T10:00:00+09:00"]);
+        assert_eq!(parser.parse(&record).unwrap(),
+            Some(Record::from(vec![
+                Value::Integer(12),
+                Value::Bool(true),
+                Value::Time(chrono::NaiveTime::from_hms(10, 30, 0)),
+                Value::DateTime(NaiveDate::from_ymd(2020, 9, 12).and_hms(1, 0, 0)),
+            ])));
+
+        let record = StringRecord::from(vec!["1.5", "no", "10:30", "2020-09-12T10:00:00"]);
+        assert!(parser.parse(&record).is_err());
+        let record = StringRecord::from(vec!["1", "maybe", "10:30", "2020-09-12T10:00:00"]);
+        assert!(parser.parse(&record).is_err());
+    }
+
+    #[test]
+    fn parse_selected_fields() {
+        let columns: Columns = "3:price:Decimal, 1:date:Date".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+
+        let record = StringRecord::from(vec!["2020/09/12", "Lunch", "-1000", "JPY", ""]);
+        assert_eq!(parser.parse(&record).unwrap(),
+            Some(Record::from(vec![
+                Value::Decimal((-1000).into()),
+                Value::Date(NaiveDate::from_ymd(2020, 9, 12)),
+            ])));
+
+        let record = StringRecord::from(vec!["2020/09/12", "Lunch"]);
+        assert!(parser.parse(&record).is_err());
+
+        let columns: Columns = "Amount:price:Decimal".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+        assert!(parser.without_header().is_err());
+
+        let header = StringRecord::from(vec!["Date", "Memo", "Amount"]);
+        let parser = parser.with_header(&header).unwrap();
+        let record = StringRecord::from(vec!["2020/09/12", "Lunch", "-1000", "JPY"]);
+        assert_eq!(parser.parse(&record).unwrap(),
+            Some(Record::from(vec![Value::Decimal((-1000).into())])));
     }
 }
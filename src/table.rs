@@ -1,15 +1,19 @@
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::io::Read;
+use std::io::{Read, BufRead, BufReader, Cursor};
 use std::fs::File;
-use csv::ReaderBuilder;
+use csv::{ReaderBuilder, WriterBuilder, StringRecord};
 use thiserror::Error;
 use log::warn;
 use crate::columns::Columns;
+use crate::encoding::Encoding;
+use crate::expr::Filter;
 use crate::record::{RecordParserBuilder, RecordParser, Record};
 
 #[derive(Error,Debug)]
 pub enum Error {
+    #[error("Failed to resolve columns by the header")]
+    Header(#[source] crate::record::ParseError),
     #[error("Failed to parse a record: line {line}")]
     ParseRecord {
         source: crate::record::ParseError,
@@ -17,45 +21,151 @@ pub enum Error {
     },
     #[error("Failed to open a file")]
     FileOpen(#[from] std::io::Error),
+    #[error("Failed to decode an input")]
+    Decode(#[from] crate::encoding::DecodeError),
+    #[error("Failed to skip leading lines")]
+    SkipLines(#[source] std::io::Error),
     #[error("Failed to read a CSV record")]
     Csv(#[from] csv::Error),
 }
 
-pub fn read_csv_records<'a, R: Read>(rdr: R, records: &mut Vec<Record>, parser: &RecordParser<'a>, ignore_parse_error: bool) -> Result<(), Error> {
-    let rdr = ReaderBuilder::new()
-        .has_headers(false)
-        .flexible(true)
-        .from_reader(rdr);
+/// Dialect of a CSV file
+#[derive(Debug,Clone)]
+pub struct CsvFormat {
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>,
+    double_quote: bool,
+    comment: Option<u8>,
+    skip_lines: usize,
+    has_header: bool,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        CsvFormat {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            double_quote: true,
+            comment: None,
+            skip_lines: 0,
+            has_header: false,
+        }
+    }
+}
+
+impl CsvFormat {
+    fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .double_quote(self.double_quote)
+            .comment(self.comment)
+            .has_headers(self.has_header)
+            .flexible(true);
+        builder
+    }
+
+    /// Returns a builder of writers which write records in the same dialect
+    pub fn writer_builder(&self) -> WriterBuilder {
+        let mut builder = WriterBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .double_quote(self.double_quote)
+            .flexible(true);
+        if let Some(escape) = self.escape {
+            builder.escape(escape);
+        }
+        builder
+    }
+}
+
+/// Counters of records read from input files
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub struct LoadStats {
+    /// Number of CSV records read, excluding headers
+    pub read: usize,
+    /// Number of records skipped by `ignore_length_mismatch`
+    pub skipped_length_mismatch: usize,
+    /// Number of records skipped by `ignore_parse_error`
+    pub skipped_parse_error: usize,
+    /// Number of records excluded by a filter
+    pub filtered: usize,
+}
+
+fn decode<'r, R: Read + 'r>(rdr: R, encoding: Option<Encoding>) -> Result<Box<dyn Read + 'r>, Error> {
+    match encoding {
+        Some(encoding) => Ok(Box::new(Cursor::new(encoding.decode(rdr)?))),
+        None => Ok(Box::new(crate::encoding::skip_utf8_bom(rdr).map_err(crate::encoding::DecodeError::Io)?)),
+    }
+}
+
+/// Reads records satisfying `filter` from `rdr` and returns the header if the format has one
+pub fn read_csv_records<R: Read>(rdr: R, records: &mut Vec<Record>, stats: &mut LoadStats, parser: &RecordParser, format: &CsvFormat, filter: Option<&Filter>, ignore_parse_error: bool) -> Result<Option<StringRecord>, Error> {
+    let mut rdr = BufReader::new(rdr);
+    let mut buf = Vec::new();
+    for _ in 0..format.skip_lines {
+        buf.clear();
+        rdr.read_until(b'\n', &mut buf).map_err(Error::SkipLines)?;
+    }
+
+    let mut rdr = format.reader_builder().from_reader(rdr);
+
+    let header_parser;
+    let mut header = None;
+    let parser = if format.has_header {
+        let fields = rdr.headers().map_err(Error::Csv)?.clone();
+        header_parser = parser.with_header(&fields).map_err(Error::Header)?;
+        header = Some(fields);
+        &header_parser
+    } else {
+        parser.without_header().map_err(Error::Header)?;
+        parser
+    };
 
     for (index, record) in rdr.into_records().enumerate() {
-        let record = record.map_err(|e| Error::Csv(e))?;
+        let record = record.map_err(Error::Csv)?;
+        stats.read += 1;
+        let line = format.skip_lines + record.position()
+            .map(|pos| pos.line() as usize)
+            .unwrap_or(index+1);
         let record = match parser.parse(&record) {
             Ok(record) => record,
             Err(e) => {
                 if ignore_parse_error {
-                    warn!("Ignored a parse error: {} at line {}; {:?}", e, index+1, record);
+                    warn!("Ignored a parse error: {} at line {}; {:?}", e, line, record);
+                    stats.skipped_parse_error += 1;
                     continue;
                 } else {
                     return Err(Error::ParseRecord {
                         source: e,
-                        line: index+1,
+                        line,
                     });
                 }
             },
         };
-        if let Some(record) = record {
-            records.push(record);
+        match (record, filter) {
+            (None, _) => stats.skipped_length_mismatch += 1,
+            (Some(record), Some(filter)) if !filter.test(&record) => stats.filtered += 1,
+            (Some(record), _) => records.push(record),
         }
     }
 
-    Ok(())
+    Ok(header)
 }
 
 #[derive(Debug,Default)]
 pub struct TableBuilder {
     columns: Option<Columns>,
+    format: CsvFormat,
+    encoding: Option<Encoding>,
     ignore_length_mismatch: bool,
     ignore_parse_error: bool,
+    filter: Option<Filter>,
 }
 
 impl TableBuilder {
@@ -70,6 +180,69 @@ impl TableBuilder {
         }
     }
 
+    pub fn has_header(self, flag: bool) -> Self {
+        TableBuilder {
+            format: CsvFormat { has_header: flag, ..self.format },
+            ..self
+        }
+    }
+
+    /// Sets the character encoding of input files
+    ///
+    /// If `None` is given, inputs are read as UTF-8 without conversion.
+    pub fn encoding(self, encoding: Option<Encoding>) -> Self {
+        TableBuilder {
+            encoding,
+            ..self
+        }
+    }
+
+    pub fn delimiter(self, delimiter: u8) -> Self {
+        TableBuilder {
+            format: CsvFormat { delimiter, ..self.format },
+            ..self
+        }
+    }
+
+    pub fn quote(self, quote: u8) -> Self {
+        TableBuilder {
+            format: CsvFormat { quote, ..self.format },
+            ..self
+        }
+    }
+
+    /// Sets an escape character for quotes in quoted fields
+    pub fn escape(self, escape: Option<u8>) -> Self {
+        TableBuilder {
+            format: CsvFormat { escape, ..self.format },
+            ..self
+        }
+    }
+
+    /// Sets whether two consecutive quotes in a quoted field mean a quote
+    pub fn double_quote(self, flag: bool) -> Self {
+        TableBuilder {
+            format: CsvFormat { double_quote: flag, ..self.format },
+            ..self
+        }
+    }
+
+    /// Sets a character which starts a comment line
+    pub fn comment(self, comment: Option<u8>) -> Self {
+        TableBuilder {
+            format: CsvFormat { comment, ..self.format },
+            ..self
+        }
+    }
+
+    /// Sets the number of lines skipped at the beginning of each file
+    pub fn skip_lines(self, lines: usize) -> Self {
+        TableBuilder {
+            format: CsvFormat { skip_lines: lines, ..self.format },
+            ..self
+        }
+    }
+
     pub fn ignore_length_mismatch(self, flag: bool) -> Self {
         TableBuilder {
             ignore_length_mismatch: flag,
@@ -84,6 +257,14 @@ impl TableBuilder {
         }
     }
 
+    /// Sets a condition which records must satisfy to be read
+    pub fn filter(self, filter: Option<Filter>) -> Self {
+        TableBuilder {
+            filter,
+            ..self
+        }
+    }
+
     pub fn from_path<P: AsRef<Path>>(self, input: &[P]) -> Result<Table, Error> {
         let hyphen = PathBuf::from_str("-").unwrap();
 
@@ -94,22 +275,30 @@ impl TableBuilder {
             .from_columns(&columns);
 
         let mut data = Vec::new();
+        let mut stats = LoadStats::default();
+        let mut header = None;
 
         for path in input.iter() {
             let path = path.as_ref();
 
-            if path == &hyphen {
-                read_csv_records(std::io::stdin(), &mut data, &parser, self.ignore_parse_error)?;
+            let file_header = if path == hyphen {
+                let rdr = decode(std::io::stdin(), self.encoding)?;
+                read_csv_records(rdr, &mut data, &mut stats, &parser, &self.format, self.filter.as_ref(), self.ignore_parse_error)?
             } else {
-                let f = File::open(&path)
-                    .map_err(|e| Error::FileOpen(e))?;
-                read_csv_records(f, &mut data, &parser, self.ignore_parse_error)?;
-            }
+                let f = File::open(path)
+                    .map_err(Error::FileOpen)?;
+                let rdr = decode(f, self.encoding)?;
+                read_csv_records(rdr, &mut data, &mut stats, &parser, &self.format, self.filter.as_ref(), self.ignore_parse_error)?
+            };
+            header = header.or(file_header);
         }
 
         Ok(Table {
-            columns: columns,
-            data: data,
+            columns,
+            format: self.format,
+            header,
+            data,
+            stats,
         })
     }
 }
@@ -117,7 +306,10 @@ impl TableBuilder {
 #[derive(Debug,Clone)]
 pub struct Table {
     columns: Columns,
+    format: CsvFormat,
+    header: Option<StringRecord>,
     data: Vec<Record>,
+    stats: LoadStats,
 }
 
 impl Table {
@@ -125,6 +317,20 @@ impl Table {
         &self.columns
     }
 
+    /// Returns the dialect of the input files
+    pub fn format(&self) -> &CsvFormat {
+        &self.format
+    }
+
+    /// Returns the header of the first file
+    pub fn header(&self) -> Option<&StringRecord> {
+        self.header.as_ref()
+    }
+
+    pub fn stats(&self) -> LoadStats {
+        self.stats
+    }
+
     pub fn inner(&self) -> &[Record] {
         &self.data
     }
@@ -133,3 +339,94 @@ impl Table {
         self.data.iter()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Value;
+
+    #[test]
+    fn read_with_format() {
+        let columns: Columns = "date:Date, price:Decimal".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+        let format = CsvFormat {
+            delimiter: b';',
+            quote: b'\'',
+            comment: Some(b'#'),
+            skip_lines: 1,
+            ..CsvFormat::default()
+        };
+        let input = "Statement for September\n# date;price\n2020/09/05;'-2000'\n";
+
+        let mut records = Vec::new();
+        let mut stats = LoadStats::default();
+        read_csv_records(input.as_bytes(), &mut records, &mut stats, &parser, &format, None, false).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(stats.read, 1);
+        assert_eq!(records[0][1], Value::Decimal((-2000).into()));
+
+        let mut wtr = format.writer_builder().from_writer(Vec::new());
+        wtr.write_record(vec!["2020/09/05", "a;b", "-2000"]).unwrap();
+        assert_eq!(String::from_utf8(wtr.into_inner().unwrap()).unwrap(), "2020/09/05;'a;b';-2000\n");
+    }
+
+    #[test]
+    fn read_header_with_bom() {
+        let columns: Columns = "date:Date, price:Decimal".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+        let format = CsvFormat {
+            has_header: true,
+            ..CsvFormat::default()
+        };
+        let input = "\u{FEFF}price,date\n-2000,2020/09/05\n";
+
+        let mut records = Vec::new();
+        let mut stats = LoadStats::default();
+        let rdr = decode(input.as_bytes(), None).unwrap();
+        read_csv_records(rdr, &mut records, &mut stats, &parser, &format, None, false).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0][1], Value::Decimal((-2000).into()));
+    }
+
+    #[test]
+    fn count_skipped() {
+        let columns: Columns = "date:Date, price:Decimal".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(true)
+            .from_columns(&columns);
+        let input = "2020/09/05,-2000\n2020/09/06\nyesterday,-100\n2020/09/07,-300\n";
+
+        let mut records = Vec::new();
+        let mut stats = LoadStats::default();
+        read_csv_records(input.as_bytes(), &mut records, &mut stats, &parser, &CsvFormat::default(), None, true).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(stats, LoadStats {
+            read: 4,
+            skipped_length_mismatch: 1,
+            skipped_parse_error: 1,
+            filtered: 0,
+        });
+    }
+
+    #[test]
+    fn filter() {
+        let columns: Columns = "date:Date, description, price:Decimal?".parse().unwrap();
+        let parser = RecordParserBuilder::new()
+            .ignore_length_mismatch(false)
+            .from_columns(&columns);
+        let filter = crate::expr::parse_filter(r#"price != 0 and not description matches "^Opening""#, &columns).unwrap();
+        let input = "2020/09/01,Opening balance,10000\n2020/09/05,Book,-2000\n2020/09/06,Authorization,0\n2020/09/07,Pending,\n";
+
+        let mut records = Vec::new();
+        let mut stats = LoadStats::default();
+        read_csv_records(input.as_bytes(), &mut records, &mut stats, &parser, &CsvFormat::default(), Some(&filter), false).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0][1], Value::String("Book".to_owned()));
+        assert_eq!(stats.read, 4);
+        assert_eq!(stats.filtered, 3);
+    }
+}